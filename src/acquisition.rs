@@ -0,0 +1,176 @@
+use std::{fmt, pin::Pin, task::{Context, Poll}, thread, time::{Duration, Instant}};
+use futures_core::Stream;
+use log::{error, info};
+use serialport::SerialPort;
+use tokio::sync::mpsc;
+use crate::reading::Reading;
+
+pub const BAUD_RATE: u32 = 19200;
+
+/// If no data comes from the device for this duration, the break signal is sent to wake it up.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AcquisitionErr {
+  /// The data from the device is broken. The stream continues after this error.
+  Parse(es51986::parser::ParseError),
+  /// The serial port cannot be accessed. The stream ends after this error.
+  SerialPort(String),
+}
+
+impl fmt::Display for AcquisitionErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AcquisitionErr::Parse(err) => write!(f, "Cannot parse data: {:?}", err),
+      AcquisitionErr::SerialPort(msg) => write!(f, "Cannot access serial port: {}", msg),
+    }
+  }
+}
+
+pub fn open_serialport(port: &str) -> Result<Box<dyn SerialPort>, AcquisitionErr> {
+  serialport::new(port, BAUD_RATE)
+  .data_bits(serialport::DataBits::Seven)
+  .parity(serialport::Parity::Odd)
+  .stop_bits(serialport::StopBits::One)
+  .timeout(Duration::from_millis(1000))
+  .open()
+  .map_err(|e| AcquisitionErr::SerialPort(e.to_string()))
+}
+
+/// Send break signal to the device. The M-6000M starts sending data when it receives the break signal.
+pub fn send_break(ser: &dyn SerialPort) -> Result<(), AcquisitionErr> {
+  ser.set_break().map_err(|e| AcquisitionErr::SerialPort(format!("Cannot send break signal {:?}", e)))?;
+  thread::sleep(Duration::from_millis(1000));
+  if let Err(err) = ser.clear_break() {
+    error!("Serial port access error. Cannot send break signal {:?}", err);
+    thread::sleep(Duration::from_millis(1000));
+  }
+  Ok(())
+}
+
+fn send_break_if_needed(ser: &dyn SerialPort, last_received: Instant, timeout: Duration) -> Result<bool, AcquisitionErr> {
+  let elapsed: Duration = last_received.elapsed();
+
+  if timeout < elapsed {
+    info!("No data comes from the device. Sending break signal...");
+    send_break(ser)?;
+    info!("Sending break signal done.");
+    Ok(true)
+  } else {
+    Ok(false)
+  }
+}
+
+/// Start reading the serial port in a blocking task. The received bytes are sent to the returned receiver. If no data
+/// comes for the timeout duration, the break signal is sent. The receiver gets an error and then is closed if the
+/// serial port fails. Reading stops when the receiver is dropped.
+pub fn launch_serialport_worker(mut ser: Box<dyn SerialPort>, timeout: Duration) -> mpsc::UnboundedReceiver<Result<Vec<u8>, AcquisitionErr>> {
+  let (tx, rx) = mpsc::unbounded_channel();
+  tokio::task::spawn_blocking(move || {
+    let mut last_received_time: Instant = Instant::now();
+    let mut buf: [u8; 64] = [0; 64];
+    while !tx.is_closed() {
+      let woke_up = match ser.read(&mut buf) {
+        Ok(0) => send_break_if_needed(ser.as_ref(), last_received_time, timeout),
+        Ok(read_size) => {
+          last_received_time = Instant::now();
+          if tx.send(Ok(buf[0..read_size].to_vec())).is_err() {
+            break;
+          }
+          Ok(false)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::TimedOut => send_break_if_needed(ser.as_ref(), last_received_time, timeout),
+        Err(err) => Err(AcquisitionErr::SerialPort(format!("Cannot receive from serial port: {:?}", err))),
+      };
+      match woke_up {
+        Ok(true) => last_received_time = Instant::now(),
+        Ok(false) => {}
+        Err(err) => {
+          let _ = tx.send(Err(err));
+          return;
+        }
+      }
+    }
+    info!("Receiver is closed. Stop reading serial port.");
+  });
+  rx
+}
+
+/// Stream of the readings from the device.
+///
+/// ```no_run
+/// # async fn run() {
+/// use m6000m_rs::acquisition;
+///
+/// let ser = acquisition::open_serialport("/dev/ttyUSB0").unwrap();
+/// let mut readings = acquisition::ReadingStream::new(acquisition::launch_serialport_worker(ser, acquisition::DEFAULT_TIMEOUT));
+/// while let Some(reading) = readings.next().await {
+///   println!("{:?}", reading);
+/// }
+/// # }
+/// ```
+pub struct ReadingStream {
+  rx: mpsc::UnboundedReceiver<Result<Vec<u8>, AcquisitionErr>>,
+  parser: es51986::parser::Parser,
+  pending: std::collections::VecDeque<Result<Reading, AcquisitionErr>>,
+}
+
+impl ReadingStream {
+  pub fn new(rx: mpsc::UnboundedReceiver<Result<Vec<u8>, AcquisitionErr>>) -> Self {
+    Self { rx, parser: es51986::parser::Parser::new(), pending: std::collections::VecDeque::new() }
+  }
+
+  /// Open the serial port and start reading.
+  pub fn open(port: &str) -> Result<Self, AcquisitionErr> {
+    Ok(Self::new(launch_serialport_worker(open_serialport(port)?, DEFAULT_TIMEOUT)))
+  }
+
+  pub async fn next(&mut self) -> Option<Result<Reading, AcquisitionErr>> {
+    std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+  }
+}
+
+impl Stream for ReadingStream {
+  type Item = Result<Reading, AcquisitionErr>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    loop {
+      if let Some(r) = self.pending.pop_front() {
+        return Poll::Ready(Some(r));
+      }
+      match self.rx.poll_recv(cx) {
+        Poll::Ready(Some(Ok(bytes))) => {
+          let parsed: Vec<Result<Reading, AcquisitionErr>> = self.parser.parse(&bytes).into_iter()
+            .map(|r| r.map(Reading::new).map_err(AcquisitionErr::Parse))
+            .collect();
+          self.pending.extend(parsed);
+        }
+        Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+        Poll::Ready(None) => return Poll::Ready(None),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tokio::sync::mpsc;
+  use crate::reading::Reading;
+  use super::{AcquisitionErr, ReadingStream};
+
+  #[tokio::test]
+  async fn readings_from_bytes() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let mut stream = ReadingStream::new(rx);
+    tx.send(Ok(b"1098538".to_vec())).unwrap();
+    tx.send(Ok(b"02\r\n00002;80:\r\n".to_vec())).unwrap();
+    tx.send(Err(AcquisitionErr::SerialPort("unplugged".to_owned()))).unwrap();
+    drop(tx);
+
+    assert_eq!(stream.next().await, Some(Ok(Reading::parse_frame(b"109853802"))));
+    assert_eq!(stream.next().await, Some(Ok(Reading::parse_frame(b"00002;80:"))));
+    assert_eq!(stream.next().await, Some(Err(AcquisitionErr::SerialPort("unplugged".to_owned()))));
+    assert_eq!(stream.next().await, None);
+  }
+}
@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::{audio::Sound, reading::{self, Reading}};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Condition {
+  /// Raised when the value exceeds the limit.
+  Above {
+    #[serde(deserialize_with = "reading::deserialize_quantity")]
+    limit: f64,
+  },
+  /// Raised when the value falls below the limit.
+  Below {
+    #[serde(deserialize_with = "reading::deserialize_quantity")]
+    limit: f64,
+  },
+  /// Raised when the value goes out of the band.
+  Outside {
+    #[serde(deserialize_with = "reading::deserialize_quantity")]
+    low: f64,
+    #[serde(deserialize_with = "reading::deserialize_quantity")]
+    high: f64,
+  },
+}
+
+impl Condition {
+  fn violated(&self, value: f64) -> bool {
+    match self {
+      Condition::Above { limit } => *limit < value,
+      Condition::Below { limit } => value < *limit,
+      Condition::Outside { low, high } => value < *low || *high < value,
+    }
+  }
+
+  /// The value is back inside the limits by the hysteresis.
+  fn recovered(&self, value: f64, hysteresis: f64) -> bool {
+    match self {
+      Condition::Above { limit } => value <= limit - hysteresis,
+      Condition::Below { limit } => limit + hysteresis <= value,
+      Condition::Outside { low, high } => low + hysteresis <= value && value <= high - hysteresis,
+    }
+  }
+}
+
+/// Alarm rule. The limits are in the base unit (V, A, Ohm, Hz, F).
+///
+/// ```toml
+/// [[alarms]]
+/// name = "5V rail"
+/// function = "Voltage"
+/// type = "outside"
+/// low = 4.75
+/// high = 5.25
+/// hysteresis = "10m"
+/// min-duration = 2.0
+/// sound = "speak"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AlarmRule {
+  pub name: String,
+  /// The rule applies only to this function. Applies to any function if omitted.
+  #[serde(default)]
+  pub function: Option<es51986::Function>,
+  #[serde(flatten)]
+  pub condition: Condition,
+  /// The alarm is cleared when the value is back inside the limits by this amount.
+  #[serde(default, deserialize_with = "reading::deserialize_quantity")]
+  pub hysteresis: f64,
+  /// The alarm is raised when the condition continues for this duration (seconds).
+  #[serde(default)]
+  pub min_duration: f64,
+  #[serde(default)]
+  pub sound: Sound,
+  /// Message to speak. The name is used if omitted.
+  #[serde(default)]
+  pub message: Option<String>,
+}
+
+impl AlarmRule {
+  pub fn message(&self) -> &str {
+    self.message.as_deref().unwrap_or(&self.name)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlarmEventKind {
+  Raised,
+  Cleared,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlarmEvent {
+  pub name: String,
+  pub kind: AlarmEventKind,
+  /// The value in the base unit that caused the event.
+  pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+  Normal,
+  Pending(Instant),
+  Active,
+}
+
+/// Evaluates the alarm rules on each reading.
+pub struct Alarms {
+  rules: Vec<(AlarmRule, State)>,
+}
+
+impl Alarms {
+  pub fn new(rules: Vec<AlarmRule>) -> Self {
+    Self { rules: rules.into_iter().map(|r| (r, State::Normal)).collect() }
+  }
+
+  pub fn rules(&self) -> impl Iterator<Item = &AlarmRule> {
+    self.rules.iter().map(|(r, _)| r)
+  }
+
+  /// Evaluate the reading received at now. The names of the active alarms for the function of the reading are set to it.
+  pub fn evaluate(&mut self, reading: &mut Reading, now: Instant) -> Vec<AlarmEvent> {
+    let mut events = vec![];
+    let value = reading.normalized();
+    for (rule, state) in self.rules.iter_mut() {
+      let applies = rule.function.as_ref().map(|f| f == &reading.raw.function).unwrap_or(true);
+      if let (true, Some(value)) = (applies, value) {
+        match state.clone() {
+          State::Normal | State::Pending(_) if !rule.condition.violated(value) => *state = State::Normal,
+          State::Normal | State::Pending(_) => {
+            let since = match state { State::Pending(since) => *since, _ => now };
+            if Duration::from_secs_f64(rule.min_duration.max(0.0)) <= now.duration_since(since) {
+              *state = State::Active;
+              events.push(AlarmEvent { name: rule.name.clone(), kind: AlarmEventKind::Raised, value });
+            } else {
+              *state = State::Pending(since);
+            }
+          }
+          State::Active => {
+            if rule.condition.recovered(value, rule.hysteresis) {
+              *state = State::Normal;
+              events.push(AlarmEvent { name: rule.name.clone(), kind: AlarmEventKind::Cleared, value });
+            }
+          }
+        }
+      }
+      if applies && *state == State::Active {
+        reading.alarms.push(rule.name.clone());
+      }
+    }
+    events
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use crate::reading::Reading;
+  use crate::audio::Sound;
+  use super::{AlarmEvent, AlarmEventKind, AlarmRule, Alarms, Condition};
+
+  fn rule(condition: Condition, hysteresis: f64, min_duration: f64) -> AlarmRule {
+    AlarmRule {
+      name: "rail".to_owned(), function: Some(es51986::Function::Voltage), condition, hysteresis, min_duration,
+      sound: Sound::None, message: None,
+    }
+  }
+
+  #[test]
+  fn hysteresis() {
+    let mut alarms = Alarms::new(vec![rule(Condition::Above { limit: 5.0 }, 0.1, 0.0)]);
+    let now = Instant::now();
+    let mut r = Reading::parse_frame(b"05000;80:");
+    assert_eq!(alarms.evaluate(&mut r, now), vec![]);
+    assert!(r.alarms.is_empty());
+
+    let mut r = Reading::parse_frame(b"05010;80:");
+    assert_eq!(alarms.evaluate(&mut r, now), vec![AlarmEvent { name: "rail".to_owned(), kind: AlarmEventKind::Raised, value: 5.01 }]);
+    assert_eq!(r.alarms, vec!["rail".to_owned()]);
+
+    let mut r = Reading::parse_frame(b"04950;80:");
+    assert_eq!(alarms.evaluate(&mut r, now), vec![]);
+    assert_eq!(r.alarms, vec!["rail".to_owned()]);
+
+    let mut r = Reading::parse_frame(b"04900;80:");
+    assert_eq!(alarms.evaluate(&mut r, now), vec![AlarmEvent { name: "rail".to_owned(), kind: AlarmEventKind::Cleared, value: 4.9 }]);
+    assert!(r.alarms.is_empty());
+  }
+
+  #[test]
+  fn parse() {
+    #[derive(serde::Deserialize)]
+    struct Config {
+      alarms: Vec<AlarmRule>,
+    }
+    let config: Config = toml::from_str(r#"
+[[alarms]]
+name = "5V rail"
+function = "Voltage"
+type = "outside"
+low = 4.75
+high = 5.25
+hysteresis = "10m"
+min-duration = 2.0
+sound = "speak"
+
+[[alarms]]
+name = "leak"
+type = "above"
+limit = "1u"
+"#).unwrap();
+    assert_eq!(config.alarms[0].condition, Condition::Outside { low: 4.75, high: 5.25 });
+    assert_eq!(config.alarms[0].hysteresis, 0.01);
+    assert_eq!(config.alarms[0].sound, Sound::Speak);
+    assert_eq!(config.alarms[1].condition, Condition::Above { limit: 1e-6 });
+    assert_eq!(config.alarms[1].function, None);
+    assert_eq!(config.alarms[1].sound, Sound::Beep);
+    assert_eq!(config.alarms[1].message(), "leak");
+  }
+
+  #[test]
+  fn min_duration() {
+    let mut alarms = Alarms::new(vec![rule(Condition::Outside { low: 4.75, high: 5.25 }, 0.0, 2.0)]);
+    let now = Instant::now();
+    assert_eq!(alarms.evaluate(&mut Reading::parse_frame(b"04500;80:"), now), vec![]);
+    assert_eq!(alarms.evaluate(&mut Reading::parse_frame(b"04500;80:"), now + Duration::from_secs(1)), vec![]);
+    // Recovered before the duration.
+    assert_eq!(alarms.evaluate(&mut Reading::parse_frame(b"05000;80:"), now + Duration::from_secs(2)), vec![]);
+    assert_eq!(alarms.evaluate(&mut Reading::parse_frame(b"04500;80:"), now + Duration::from_secs(3)), vec![]);
+    assert_eq!(alarms.evaluate(&mut Reading::parse_frame(b"04500;80:"), now + Duration::from_secs(5)).len(), 1);
+    // Other functions are not evaluated.
+    let mut ohm = Reading::parse_frame(b"109853802");
+    assert_eq!(alarms.evaluate(&mut ohm, now + Duration::from_secs(6)), vec![]);
+    assert!(ohm.alarms.is_empty());
+  }
+}
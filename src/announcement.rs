@@ -0,0 +1,178 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::reading::Reading;
+
+#[derive(ValueEnum, Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+  #[default]
+  Ja,
+  En,
+}
+
+/// Words to announce the readings in a language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phrases {
+  pub template: &'static str,
+  pub minus: &'static str,
+  pub overload: &'static str,
+  pub ac: &'static str,
+  pub dc: &'static str,
+  pub battery: &'static str,
+  /// Appended to the alarm message when the alarm is cleared.
+  pub cleared: &'static str,
+  pub mega: &'static str,
+  pub kilo: &'static str,
+  pub milli: &'static str,
+  pub micro: &'static str,
+  pub nano: &'static str,
+  pub ampere: &'static str,
+  pub volt: &'static str,
+  pub ohm: &'static str,
+  pub hertz: &'static str,
+  pub farad: &'static str,
+}
+
+const JA: Phrases = Phrases {
+  template: "{sign}{value}{prefix}{unit} {acdc} {battery}",
+  minus: "マイナス",
+  overload: "オーバーロード",
+  ac: "交流",
+  dc: "直流",
+  battery: "電池残量低下",
+  cleared: "解除",
+  mega: "メガ",
+  kilo: "キロ",
+  milli: "ミリ",
+  micro: "マイクロ",
+  nano: "ナノ",
+  ampere: "アンペア",
+  volt: "ボルト",
+  ohm: "オーム",
+  hertz: "ヘルツ",
+  farad: "ファラッド",
+};
+
+const EN: Phrases = Phrases {
+  template: "{sign} {value} {prefix}{unit} {acdc}. {battery}",
+  minus: "minus",
+  overload: "overload",
+  ac: "AC",
+  dc: "DC",
+  battery: "low battery",
+  cleared: "cleared",
+  mega: "mega",
+  kilo: "kilo",
+  milli: "milli",
+  micro: "micro",
+  nano: "nano",
+  ampere: "amperes",
+  volt: "volts",
+  ohm: "ohms",
+  hertz: "hertz",
+  farad: "farads",
+};
+
+impl Locale {
+  pub fn phrases(&self) -> &'static Phrases {
+    match self {
+      Locale::Ja => &JA,
+      Locale::En => &EN,
+    }
+  }
+}
+
+/// Builds the text to speak from a reading. The template can contain {sign}, {value}, {prefix}, {unit}, {acdc} and
+/// {battery}. On overload, {value} is the overload word and {sign}, {prefix} and {unit} are empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announcer {
+  phrases: &'static Phrases,
+  template: String,
+}
+
+impl Announcer {
+  /// The template of the locale is used if template is None.
+  pub fn new(locale: &Locale, template: Option<String>) -> Self {
+    let phrases = locale.phrases();
+    Self { phrases, template: template.unwrap_or_else(|| phrases.template.to_owned()) }
+  }
+
+  pub fn phrases(&self) -> &'static Phrases {
+    self.phrases
+  }
+
+  /// None if there is nothing to announce.
+  pub fn text(&self, reading: &Reading) -> Option<String> {
+    let p = self.phrases;
+    let data = &reading.raw;
+    let (sign, value, prefix, unit) = if data.status.is_overflow {
+      ("", p.overload.to_owned(), "", "")
+    } else {
+      let value = reading.value.as_ref()?;
+      let prefix = match &value.value_unit.prefix_unit {
+        es51986::PrefixUnit::Mega => p.mega,
+        es51986::PrefixUnit::Kilo => p.kilo,
+        es51986::PrefixUnit::None => "",
+        es51986::PrefixUnit::Millis => p.milli,
+        es51986::PrefixUnit::Micro => p.micro,
+        es51986::PrefixUnit::Nano => p.nano,
+      };
+      let unit = match &value.value_unit.base_unit {
+        es51986::BaseUnit::Ampere => p.ampere,
+        es51986::BaseUnit::Volt => p.volt,
+        es51986::BaseUnit::Ohm => p.ohm,
+        es51986::BaseUnit::Hearts => p.hertz,
+        es51986::BaseUnit::Farad => p.farad,
+      };
+      let sign = if data.status.sign.clone().is_minus() { p.minus } else { "" };
+      (sign, value.digits.clone(), prefix, unit)
+    };
+    let acdc = if data.option2.is_ac { p.ac } else if data.option2.is_dc { p.dc } else { "" };
+    let battery = if data.status.is_battery_depleted { p.battery } else { "" };
+
+    let text = self.template
+      .replace("{sign}", sign)
+      .replace("{value}", &value)
+      .replace("{prefix}", prefix)
+      .replace("{unit}", unit)
+      .replace("{acdc}", acdc)
+      .replace("{battery}", battery);
+    // Remove the spaces and the periods left by the empty placeholders.
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let text = text.trim_end_matches(['.', ' ']).to_owned();
+    Some(text)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::reading::Reading;
+  use super::{Announcer, Locale};
+
+  #[test]
+  fn ja() {
+    let a = Announcer::new(&Locale::Ja, None);
+    assert_eq!(a.text(&Reading::parse_frame(b"109853802")).unwrap(), "0.985キロオーム");
+    assert_eq!(a.text(&Reading::parse_frame(b"00002;80:")).unwrap(), "0.002ボルト 直流");
+    assert_eq!(a.text(&Reading::parse_frame(b"01234;<0:")).unwrap(), "マイナス1.234ボルト 直流");
+    assert_eq!(a.text(&Reading::parse_frame(b"560003902")).unwrap(), "オーバーロード");
+  }
+
+  #[test]
+  fn en() {
+    let a = Announcer::new(&Locale::En, None);
+    assert_eq!(a.text(&Reading::parse_frame(b"109853802")).unwrap(), "0.985 kiloohms");
+    assert_eq!(a.text(&Reading::parse_frame(b"01234;<0:")).unwrap(), "minus 1.234 volts DC");
+    assert_eq!(a.text(&Reading::parse_frame(b"560003902")).unwrap(), "overload");
+
+    let mut r = Reading::parse_frame(b"00002;80:");
+    r.raw.status.is_battery_depleted = true;
+    assert_eq!(a.text(&r).unwrap(), "0.002 volts DC. low battery");
+  }
+
+  #[test]
+  fn template() {
+    let a = Announcer::new(&Locale::En, Some("{value} {prefix}{unit}".to_owned()));
+    assert_eq!(a.text(&Reading::parse_frame(b"00002;80:")).unwrap(), "0.002 volts");
+  }
+}
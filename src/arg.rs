@@ -1,41 +1,465 @@
-use clap::{arg, command, Parser, ValueEnum};
+use std::{fmt, path::PathBuf};
 
-#[derive(ValueEnum, Debug, PartialEq, Clone)]
-pub enum OutputFormat {
-    Jsonl,
-}
+use clap::{Parser, Subcommand, ValueEnum};
+pub use m6000m_rs::format::OutputFormat;
+use m6000m_rs::{alarm::AlarmRule, announcement::Locale, scaling::ScaleRule, audio::Sound, binning::{Bin, BinningConfig}, filter::DeadbandConfig, reading::parse_quantity, recording::RecordingConfig, smoothing::{self, SmoothingConfig, SmoothingMethod}, speech::{SpeechConfig, SpeechPolicy}, stable::StableConfig, tone::ToneConfig, tts::{Speaker, TtsConfig, VoiceTuning}};
+use serde::Deserialize;
+
+use crate::output::OutputConfig;
 
-#[derive(Parser, Debug, Clone)]
+pub const DEFAULT_OUTPUT_FORMAT: OutputFormat = OutputFormat::Jsonl;
+pub const DEFAULT_SPEAK_INTERVAL: f64 = 5.0;
+pub const DEFAULT_VOICEBOX_SPEAKER: usize = m6000m_rs::tts::DEFAULT_VOICEVOX_SPEAKER;
+
+#[derive(Parser, Debug, Clone, Default)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<SubCommand>,
     /// serial port to connect.
     #[arg(long)]
     pub port: Option<String>,
-    /// Output format.
-    #[arg(long, value_enum, default_value = "jsonl")]
-    pub output_format: OutputFormat,
+    /// Output format of the outputs that do not specify one. [default: jsonl]
+    #[arg(long, value_enum)]
+    pub output_format: Option<OutputFormat>,
     // Voicebox URL. If specified, will speak the measured data. (Example: --voice_box_udl http://localhost:50021)
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub voicebox_url: Option<String>,
-    // Voicebox speaker.
-    #[arg(long, default_value = "1")]
-    pub voicebox_speaker: usize,
-    /// Output audio device name.
+    /// Voicebox speaker. An ID or a name with an optional style (e.g. 3, ずんだもん, ずんだもん/ノーマル). [default: 1]
+    #[arg(long, value_name = "SPEAKER")]
+    pub voicebox_speaker: Option<Speaker>,
+    /// Text-to-speech engine to speak the measured data. The voicevox engine uses --voicebox-url. [default: voicevox if --voicebox-url is given]
+    #[arg(long, value_enum, value_name = "ENGINE")]
+    pub tts: Option<TtsEngine>,
+    /// Voice of espeak-ng (e.g. en-us) or HTS voice file of open-jtalk.
+    #[arg(long, value_name = "VOICE")]
+    pub tts_voice: Option<String>,
+    /// Command of the command engine, split like a shell (quote the words containing spaces). {text} is replaced with the text and {output} with the WAV file
+    /// to be written. Without them the text is given to stdin and the WAV is read from stdout.
+    #[arg(long, value_name = "COMMAND")]
+    pub tts_command: Option<String>,
+    /// Directory to cache the synthesized speech. The speech is cached only in memory if omitted.
+    #[arg(long, value_name = "DIR")]
+    pub tts_cache: Option<PathBuf>,
+    /// Text-to-speech engine declared in the configuration file.
+    #[arg(skip)]
+    pub tts_config: Option<TtsConfig>,
+    /// Speed of the VOICEVOX speech. 1.0 is the normal speed.
+    #[arg(long, value_name = "SCALE")]
+    pub voice_speed: Option<f64>,
+    /// Pitch of the VOICEVOX speech. 0.0 is the normal pitch.
+    #[arg(long, value_name = "SCALE", allow_negative_numbers = true)]
+    pub voice_pitch: Option<f64>,
+    /// Intonation of the VOICEVOX speech. 1.0 is the normal intonation.
+    #[arg(long, value_name = "SCALE")]
+    pub voice_intonation: Option<f64>,
+    /// Volume of the VOICEVOX speech. 1.0 is the normal volume.
+    #[arg(long, value_name = "SCALE")]
+    pub voice_volume: Option<f64>,
+    /// Silence before the VOICEVOX speech (seconds).
+    #[arg(long, value_name = "SECONDS")]
+    pub voice_pre_silence: Option<f64>,
+    /// Silence after the VOICEVOX speech (seconds).
+    #[arg(long, value_name = "SECONDS")]
+    pub voice_post_silence: Option<f64>,
+    /// Voice tuning declared in the configuration file.
+    #[arg(skip)]
+    pub voice_tuning: Option<VoiceTuning>,
+    /// When to speak the measured data. [default: change]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub speak_on: Option<SpeakOn>,
+    /// Speak only when the value changes more than this from the last spoken one. SI prefix can be used (e.g. 10m).
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub speak_deadband: Option<f64>,
+    /// Interval of speaking with --speak-on interval (seconds). [default: 5]
+    #[arg(long, value_name = "SECONDS")]
+    pub speak_interval: Option<f64>,
+    /// Speak the same announcement again. It is skipped by default.
+    #[arg(long)]
+    pub speak_repeat: bool,
+    /// Speech scheduling declared in the configuration file.
+    #[arg(skip)]
+    pub speech_config: Option<SpeechConfig>,
+    /// Write each utterance to a WAV file named with the time in this directory.
+    #[arg(long, value_name = "DIR")]
+    pub speech_wav_dir: Option<PathBuf>,
+    /// Write all the utterances of the session to this WAV file.
+    #[arg(long, value_name = "FILE")]
+    pub speech_wav_track: Option<PathBuf>,
+    /// Do not play the speech on the audio device (e.g. on machines without audio hardware).
+    #[arg(long)]
+    pub no_speech_playback: bool,
+    /// Speech recording declared in the configuration file.
+    #[arg(skip)]
+    pub speech_recording: Option<RecordingConfig>,
+    /// Language of the announcements. [default: ja]
+    #[arg(long, value_enum)]
+    pub voice_locale: Option<Locale>,
+    /// Template of the announcements with {sign}, {value}, {prefix}, {unit}, {acdc} and {battery}.
+    #[arg(long, value_name = "TEMPLATE")]
+    pub voice_template: Option<String>,
+    /// Sound a tone instead of speaking: a beep below the resistance threshold or a pitch following the value.
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub tone: Option<ToneMode>,
+    /// Resistance below which the continuity tone sounds. SI prefix can be used. [default: 30]
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub tone_threshold: Option<f64>,
+    /// Value mapped to the lowest pitch. [default: 0]
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity, allow_negative_numbers = true)]
+    pub tone_low: Option<f64>,
+    /// Value mapped to the highest pitch. The counts on the display are used if omitted.
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity, allow_negative_numbers = true)]
+    pub tone_high: Option<f64>,
+    /// Tone declared in the configuration file.
+    #[arg(skip)]
+    pub tone_config: Option<ToneConfig>,
+    /// Output audio device: index, name or a unique part of the name shown by the list-audio-devices command.
     #[arg(long)]
     pub audio_output_device_name: Option<String>,
+    /// Configuration file. If omitted, ~/.config/m6000m/config.toml is used when it exists.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Profile in the configuration file to use.
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Enable the output of this name declared in the configuration file.
+    #[arg(long, value_name = "NAME")]
+    pub enable_output: Vec<String>,
+    /// Disable the output of this name ('stdout' turns off the standard output).
+    #[arg(long, value_name = "NAME")]
+    pub disable_output: Vec<String>,
+    /// Report statistics (min, max, mean, standard deviation and count) to stderr at exit and with the 's' command.
+    #[arg(long)]
+    pub stats: bool,
+    /// Also report statistics periodically at this interval (seconds).
+    #[arg(long, value_name = "SECONDS")]
+    pub stats_interval: Option<u64>,
+    /// REL mode. Report the difference from this reference value in the base unit. SI prefix can be used (e.g. 4.7k).
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub rel: Option<f64>,
+    /// Capture mode. Outputs receive only a single reading per probe contact when the readings settle.
+    #[arg(long)]
+    pub capture: bool,
+    /// Readings are settled when they stay within this percentage. [default: 0.5]
+    #[arg(long, value_name = "PERCENT")]
+    pub capture_tolerance: Option<f64>,
+    /// Readings are settled when they stay within the tolerance for this duration. [default: 0.5]
+    #[arg(long, value_name = "SECONDS")]
+    pub capture_duration: Option<f64>,
+    /// Readings whose absolute value is below this are treated as open probes. SI prefix can be used (e.g. 10m).
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub capture_ignore_below: Option<f64>,
+    /// Capture mode declared in the configuration file.
+    #[arg(skip)]
+    pub capture_config: Option<StableConfig>,
+    /// Outputs receive a single reading per time window of this length (seconds) with the min, max and mean of the window.
+    #[arg(long, value_name = "SECONDS")]
+    pub aggregate: Option<f64>,
+    /// Outputs receive a reading only when the value changes more than this from the last one. SI prefix can be used (e.g. 1m).
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub deadband: Option<f64>,
+    /// Outputs receive a reading only when the value changes more than this percentage from the last one.
+    #[arg(long, value_name = "PERCENT")]
+    pub deadband_percent: Option<f64>,
+    /// With the deadband, outputs receive a reading at least at this interval (seconds) even if the value does not change.
+    #[arg(long, value_name = "SECONDS")]
+    pub heartbeat: Option<f64>,
+    /// Calibration table (TOML) to correct the values with offset and gain per function, range and AC/DC.
+    #[arg(long, value_name = "PATH")]
+    pub calibration: Option<PathBuf>,
+    /// Smooth the values. The smoothed value is output alongside the raw one.
+    #[arg(long, value_enum, value_name = "METHOD")]
+    pub smooth: Option<SmoothingMethod>,
+    /// Number of the values to smooth with sma and median. [default: 5]
+    #[arg(long, value_name = "N")]
+    pub smooth_length: Option<usize>,
+    /// Weight of the new value with ema (0 < alpha <= 1). [default: 0.2]
+    #[arg(long, value_name = "ALPHA")]
+    pub smooth_alpha: Option<f64>,
+    /// Smoothing declared in the configuration file.
+    #[arg(skip)]
+    pub smoothing_config: Option<SmoothingConfig>,
+    /// Binning mode. Nominal value of the parts in the base unit. SI prefix can be used (e.g. 4.7k).
+    #[arg(long, value_name = "VALUE", value_parser = parse_quantity)]
+    pub bin_nominal: Option<f64>,
+    /// Tolerance of a bin in percent. Can be specified more than once. [default: 1 5]
+    #[arg(long, value_name = "PERCENT")]
+    pub bin_tolerance: Vec<f64>,
+    /// Log the sorted parts to this CSV file.
+    #[arg(long, value_name = "PATH")]
+    pub bin_log: Option<PathBuf>,
+    /// Binning mode declared in the configuration file.
+    #[arg(skip)]
+    pub binning: Option<BinningSettings>,
+    /// Outputs declared in the configuration file.
+    #[arg(skip)]
+    pub outputs: Option<Vec<OutputConfig>>,
+    /// Alarm rules declared in the configuration file.
+    #[arg(skip)]
+    pub alarms: Option<Vec<AlarmRule>>,
+    /// Scales declared in the configuration file.
+    #[arg(skip)]
+    pub scales: Option<Vec<ScaleRule>>,
 }
 
 impl Args {
     pub fn error(&self) -> Option<ArgsErr> {
-        if self.port.is_none() {
+        // The engine is checked first so that the port is not asked for when the options cannot work anyway.
+        if let Some(e) = self.tts_error() {
+            Some(e)
+        } else if self.port.is_none() {
             Some(ArgsErr::PortNotSpecified)
         } else {
             None
         }
     }
+
+    fn tts_error(&self) -> Option<ArgsErr> {
+        match &self.tts {
+            Some(TtsEngine::Voicevox) if self.voicebox_url.is_none() => Some(ArgsErr::VoicevoxUrlNotSpecified),
+            Some(TtsEngine::Command) => match &self.tts_command {
+                None => Some(ArgsErr::TtsCommandNotSpecified),
+                Some(cmd) => match shlex::split(cmd) {
+                    Some(words) if !words.is_empty() => None,
+                    _ => Some(ArgsErr::InvalidTtsCommand(cmd.clone())),
+                },
+            },
+            _ => None,
+        }
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format.clone().unwrap_or(DEFAULT_OUTPUT_FORMAT)
+    }
+
+    /// Settle conditions of the capture mode. None if the capture mode is off.
+    pub fn capture(&self) -> Option<StableConfig> {
+        if !self.capture && self.capture_config.is_none() {
+            return None;
+        }
+        let c = self.capture_config.clone().unwrap_or_default();
+        Some(StableConfig {
+            tolerance: self.capture_tolerance.unwrap_or(c.tolerance),
+            duration: self.capture_duration.unwrap_or(c.duration),
+            ignore_below: self.capture_ignore_below.unwrap_or(c.ignore_below),
+            ..c
+        })
+    }
+
+    /// Deadband applied to the outputs that do not declare one. None if no deadband option is given.
+    pub fn deadband(&self) -> Option<DeadbandConfig> {
+        if self.deadband.is_none() && self.deadband_percent.is_none() && self.heartbeat.is_none() {
+            return None;
+        }
+        Some(DeadbandConfig {
+            absolute: self.deadband.unwrap_or(0.0),
+            relative: self.deadband_percent.unwrap_or(0.0),
+            heartbeat: self.heartbeat,
+        })
+    }
+
+    /// Smoothing of the values. None if the smoothing is off.
+    pub fn smoothing(&self) -> Option<SmoothingConfig> {
+        let c = self.smoothing_config.clone();
+        Some(SmoothingConfig {
+            method: self.smooth.clone().or(c.as_ref().map(|c| c.method.clone()))?,
+            length: self.smooth_length.or(c.as_ref().map(|c| c.length)).unwrap_or(smoothing::length_default()),
+            alpha: self.smooth_alpha.or(c.as_ref().map(|c| c.alpha)).unwrap_or(smoothing::alpha_default()),
+        })
+    }
+
+    pub fn stats_enabled(&self) -> bool {
+        self.stats || self.stats_interval.is_some()
+    }
+
+    /// Binning mode. The options in the command line take precedence over the configuration file.
+    pub fn binning(&self) -> Option<BinningSettings> {
+        match self.bin_nominal {
+            Some(nominal) => {
+                let tolerances = if self.bin_tolerance.is_empty() { vec![1.0, 5.0] } else { self.bin_tolerance.clone() };
+                Some(BinningSettings {
+                    binning: BinningConfig {
+                        nominal,
+                        function: None,
+                        bins: tolerances.iter().map(|t| Bin { name: format!("{}%", t), tolerance: *t }).collect(),
+                        stable: StableConfig::default(),
+                    },
+                    sound: if self.tts().is_some() { Sound::Speak } else { Sound::Beep },
+                    log: self.bin_log.clone(),
+                })
+            }
+            None => self.binning.clone().map(|b| BinningSettings { log: self.bin_log.clone().or(b.log), ..b }),
+        }
+    }
+
+    /// Text-to-speech engine to speak the measured data. None if speaking is off.
+    pub fn tts(&self) -> Option<TtsConfig> {
+        let speaker = Some(self.voicebox_speaker());
+        let tuning = self.tune(VoiceTuning::default());
+        match &self.tts {
+            None => match (&self.tts_config, &self.voicebox_url) {
+                (Some(TtsConfig::Voicevox { url, speaker: s, tuning: t }), _) => Some(TtsConfig::Voicevox {
+                    url: url.clone(),
+                    speaker: self.voicebox_speaker.clone().or(s.clone()).or(speaker),
+                    tuning: self.tune(t.clone()),
+                }),
+                (Some(tts), _) => Some(tts.clone()),
+                (None, Some(url)) => Some(TtsConfig::Voicevox { url: url.clone(), speaker, tuning }),
+                (None, None) => None,
+            },
+            Some(TtsEngine::Voicevox) => self.voicebox_url.clone().map(|url| TtsConfig::Voicevox { url, speaker, tuning }),
+            Some(TtsEngine::EspeakNg) => Some(TtsConfig::EspeakNg { voice: self.tts_voice.clone() }),
+            Some(TtsEngine::OpenJtalk) => Some(TtsConfig::OpenJtalk { voice: self.tts_voice.clone().map(PathBuf::from), dictionary: None }),
+            Some(TtsEngine::Command) => Some(TtsConfig::Command {
+                command: self.tts_command.as_deref().and_then(shlex::split).unwrap_or_default(),
+            }),
+        }
+    }
+
+    /// Voice tuning with the options in the command line, then the tuning of the engine, then the voice-tuning in the
+    /// configuration file.
+    pub fn tune(&self, tuning: VoiceTuning) -> VoiceTuning {
+        VoiceTuning {
+            speed: self.voice_speed,
+            pitch: self.voice_pitch,
+            intonation: self.voice_intonation,
+            volume: self.voice_volume,
+            pre_silence: self.voice_pre_silence,
+            post_silence: self.voice_post_silence,
+        }.or(tuning).or(self.voice_tuning.clone().unwrap_or_default())
+    }
+
+    /// Speech scheduling. The options in the command line take precedence over the configuration file.
+    pub fn speech(&self) -> SpeechConfig {
+        let c = self.speech_config.clone().unwrap_or_default();
+        // --speak-on keeps the parameters in the configuration file when it selects the same mode.
+        let policy = match (&self.speak_on, c.policy) {
+            (None, policy) => policy,
+            (Some(SpeakOn::Change), policy @ SpeechPolicy::Change { .. }) => policy,
+            (Some(SpeakOn::Change), _) => SpeechPolicy::Change { deadband: 0.0, deadband_percent: 0.0 },
+            (Some(SpeakOn::Stable), policy @ SpeechPolicy::Stable(_)) => policy,
+            (Some(SpeakOn::Stable), _) => SpeechPolicy::Stable(StableConfig::default()),
+            (Some(SpeakOn::Interval), policy @ SpeechPolicy::Interval { .. }) => policy,
+            (Some(SpeakOn::Interval), _) => SpeechPolicy::Interval { seconds: DEFAULT_SPEAK_INTERVAL },
+            (Some(SpeakOn::Demand), _) => SpeechPolicy::Demand {},
+        };
+        let policy = match policy {
+            SpeechPolicy::Change { deadband, deadband_percent } => SpeechPolicy::Change { deadband: self.speak_deadband.unwrap_or(deadband), deadband_percent },
+            SpeechPolicy::Interval { seconds } => SpeechPolicy::Interval { seconds: self.speak_interval.unwrap_or(seconds) },
+            policy => policy,
+        };
+        SpeechConfig { policy, repeat: self.speak_repeat || c.repeat }
+    }
+
+    /// Where the speech is played and written. The options in the command line take precedence over the configuration file.
+    pub fn recording(&self) -> RecordingConfig {
+        let c = self.speech_recording.clone().unwrap_or_default();
+        RecordingConfig {
+            directory: self.speech_wav_dir.clone().or(c.directory),
+            track: self.speech_wav_track.clone().or(c.track),
+            playback: c.playback && !self.no_speech_playback,
+        }
+    }
+
+    /// Tone output. The options in the command line take precedence over the configuration file.
+    pub fn tone(&self) -> Option<ToneConfig> {
+        let tone = match (&self.tone, self.tone_config.clone()) {
+            (None, config) => config?,
+            (Some(ToneMode::Continuity), Some(c @ ToneConfig::Continuity { .. })) => c,
+            (Some(ToneMode::Pitch), Some(c @ ToneConfig::Pitch { .. })) => c,
+            (Some(ToneMode::Continuity), _) => ToneConfig::continuity(None),
+            (Some(ToneMode::Pitch), _) => ToneConfig::pitch(None, None),
+        };
+        Some(match tone {
+            ToneConfig::Continuity { threshold, frequency } => ToneConfig::Continuity { threshold: self.tone_threshold.unwrap_or(threshold), frequency },
+            ToneConfig::Pitch { low, high, min_frequency, max_frequency } => ToneConfig::Pitch { low: self.tone_low.unwrap_or(low), high: self.tone_high.or(high), min_frequency, max_frequency },
+        })
+    }
+
+    pub fn voice_locale(&self) -> Locale {
+        self.voice_locale.clone().unwrap_or_default()
+    }
+
+    pub fn voicebox_speaker(&self) -> Speaker {
+        self.voicebox_speaker.clone().unwrap_or(Speaker::Id(DEFAULT_VOICEBOX_SPEAKER))
+    }
+
+    /// URL of the VOICEVOX engine in use.
+    pub fn voicevox_url(&self) -> Option<String> {
+        match self.tts() {
+            Some(TtsConfig::Voicevox { url, .. }) => Some(url),
+            _ => self.voicebox_url.clone(),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum SubCommand {
+    /// List the speakers of the VOICEVOX engine (--voicebox-url) with their styles and IDs.
+    ListSpeakers,
+    /// List the audio hosts and output devices with their sample rates and formats.
+    ListAudioDevices,
+}
+
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum TtsEngine {
+    Voicevox,
+    EspeakNg,
+    OpenJtalk,
+    Command,
+}
+
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum SpeakOn {
+    /// When the value changes.
+    Change,
+    /// When the readings settle.
+    Stable,
+    /// At the interval.
+    Interval,
+    /// Only with the 'v' command.
+    Demand,
+}
+
+#[derive(ValueEnum, Debug, Clone, PartialEq)]
+pub enum ToneMode {
+    /// Beep while the resistance is below --tone-threshold.
+    Continuity,
+    /// Pitch following the value.
+    Pitch,
 }
 
 #[derive(Debug, Clone)]
 pub enum ArgsErr {
     PortNotSpecified,
-}
\ No newline at end of file
+    /// --tts voicevox without --voicebox-url.
+    VoicevoxUrlNotSpecified,
+    /// --tts command without --tts-command.
+    TtsCommandNotSpecified,
+    /// --tts-command with unbalanced quotes or no words.
+    InvalidTtsCommand(String),
+}
+
+impl fmt::Display for ArgsErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsErr::PortNotSpecified => write!(f, "--port is not specified."),
+            ArgsErr::VoicevoxUrlNotSpecified => write!(f, "--tts voicevox requires --voicebox-url."),
+            ArgsErr::TtsCommandNotSpecified => write!(f, "--tts command requires --tts-command."),
+            ArgsErr::InvalidTtsCommand(cmd) => write!(f, "Invalid --tts-command '{}'. Check the quotes.", cmd),
+        }
+    }
+}
+
+/// Binning mode with the way to announce and log the parts.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BinningSettings {
+    #[serde(flatten)]
+    pub binning: BinningConfig,
+    #[serde(default)]
+    pub sound: Sound,
+    /// CSV file to log the sorted parts.
+    #[serde(default)]
+    pub log: Option<PathBuf>,
+}
@@ -0,0 +1,53 @@
+use std::{fmt, io::Cursor, time::Duration};
+use rodio::{source::SineWave, Source};
+use serde::Deserialize;
+
+/// How an event (alarm, binning result etc.) is announced.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sound {
+  #[default]
+  Beep,
+  /// Speak with the Voicebox engine.
+  Speak,
+  None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioErr {
+  Device(String),
+  Decode(String),
+}
+
+impl fmt::Display for AudioErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AudioErr::Device(msg) => write!(f, "Cannot use audio device: {}", msg),
+      AudioErr::Decode(msg) => write!(f, "Cannot decode audio: {}", msg),
+    }
+  }
+}
+
+/// Check that an output stream can be opened on the device.
+pub fn check(device: &rodio::Device) -> Result<(), AudioErr> {
+  rodio::OutputStream::try_from_device(device).map(|_| ()).map_err(|e| AudioErr::Device(format!("{:?}", e)))
+}
+
+/// Play the source on the device and wait until the end.
+pub fn play<S>(device: &rodio::Device, source: S) -> Result<(), AudioErr>
+where S: Source + Send + 'static, S::Item: rodio::Sample + Send, f32: cpal::FromSample<S::Item> {
+  let (_stream, stream_handle) = rodio::OutputStream::try_from_device(device).map_err(|e| AudioErr::Device(format!("{:?}", e)))?;
+  let sink = rodio::Sink::try_new(&stream_handle).map_err(|e| AudioErr::Device(format!("{:?}", e)))?;
+  sink.append(source);
+  sink.sleep_until_end();
+  Ok(())
+}
+
+pub fn play_wav(device: &rodio::Device, wav: Vec<u8>) -> Result<(), AudioErr> {
+  let source = rodio::Decoder::new_wav(Cursor::new(wav)).map_err(|e| AudioErr::Decode(format!("{:?}", e)))?;
+  play(device, source)
+}
+
+pub fn beep(device: &rodio::Device, frequency: f32, duration: Duration) -> Result<(), AudioErr> {
+  play(device, SineWave::new(frequency).take_duration(duration).amplify(0.3))
+}
@@ -0,0 +1,138 @@
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use crate::{reading::{self, Reading}, stable::{StableConfig, StableDetector}};
+
+pub const REJECT_BIN_NAME: &str = "reject";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Bin {
+  pub name: String,
+  /// Tolerance in percent of the nominal value.
+  pub tolerance: f64,
+}
+
+/// Binning mode. Each part is classified into the first bin whose tolerance covers the deviation from the nominal value.
+///
+/// ```toml
+/// [binning]
+/// nominal = "4.7k"
+/// function = "Ohm"
+/// bins = [{ name = "1%", tolerance = 1.0 }, { name = "5%", tolerance = 5.0 }]
+/// stable = { tolerance = 0.2, duration = 0.5 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BinningConfig {
+  /// Nominal value in the base unit.
+  #[serde(deserialize_with = "reading::deserialize_quantity")]
+  pub nominal: f64,
+  /// Only the readings of this function are classified.
+  #[serde(default)]
+  pub function: Option<es51986::Function>,
+  pub bins: Vec<Bin>,
+  /// Conditions to regard the reading of a part as settled.
+  #[serde(default)]
+  pub stable: StableConfig,
+}
+
+/// A classified part.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Part {
+  /// Sequence number starting from 1.
+  pub number: u64,
+  pub value: f64,
+  /// (value - nominal) / nominal in percent.
+  pub deviation: f64,
+  pub bin: String,
+  /// Index of the bin. None for reject.
+  #[serde(skip)]
+  pub bin_index: Option<usize>,
+}
+
+pub struct Binning {
+  config: BinningConfig,
+  detector: StableDetector,
+  counts: Vec<u64>,
+  rejects: u64,
+}
+
+impl Binning {
+  pub fn new(mut config: BinningConfig) -> Self {
+    config.bins.sort_by(|a, b| a.tolerance.total_cmp(&b.tolerance));
+    Self {
+      detector: StableDetector::new(config.stable.clone()),
+      counts: vec![0; config.bins.len()],
+      rejects: 0,
+      config,
+    }
+  }
+
+  pub fn classify(&self, value: f64) -> (f64, Option<usize>) {
+    let deviation = if self.config.nominal == 0.0 { f64::INFINITY } else { (value - self.config.nominal) / self.config.nominal.abs() * 100.0 };
+    (deviation, self.config.bins.iter().position(|b| deviation.abs() <= b.tolerance))
+  }
+
+  /// Classify the part when its reading settles.
+  pub fn on_reading(&mut self, reading: &Reading, now: Instant) -> Option<Part> {
+    if self.config.function.as_ref().map(|f| f != &reading.raw.function).unwrap_or(false) {
+      return None;
+    }
+    if !self.detector.update(reading, now) {
+      return None;
+    }
+    let value = reading.normalized()?;
+    let (deviation, bin_index) = self.classify(value);
+    let bin = match bin_index {
+      Some(i) => {
+        self.counts[i] += 1;
+        self.config.bins[i].name.clone()
+      }
+      None => {
+        self.rejects += 1;
+        REJECT_BIN_NAME.to_owned()
+      }
+    };
+    Some(Part { number: self.total(), value, deviation, bin, bin_index })
+  }
+
+  pub fn total(&self) -> u64 {
+    self.counts.iter().sum::<u64>() + self.rejects
+  }
+
+  /// Number of the parts in each bin. The reject bin comes last.
+  pub fn counts(&self) -> Vec<(String, u64)> {
+    self.config.bins.iter().zip(self.counts.iter()).map(|(b, c)| (b.name.clone(), *c))
+      .chain(std::iter::once((REJECT_BIN_NAME.to_owned(), self.rejects)))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Instant;
+  use crate::{reading::Reading, stable::StableConfig};
+  use super::{Bin, Binning, BinningConfig};
+
+  #[test]
+  fn sort_parts() {
+    let mut binning = Binning::new(BinningConfig {
+      nominal: 4700.0,
+      function: Some(es51986::Function::Ohm),
+      bins: vec![Bin { name: "5%".to_owned(), tolerance: 5.0 }, Bin { name: "1%".to_owned(), tolerance: 1.0 }],
+      stable: StableConfig { duration: 0.0, ..Default::default() },
+    });
+    let now = Instant::now();
+    let part = binning.on_reading(&Reading::parse_frame(b"147203802"), now).unwrap();
+    assert_eq!((part.number, part.bin.as_str(), part.bin_index), (1, "1%", Some(0)));
+    // Same part.
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"147203802"), now), None);
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"560003902"), now), None);
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"149003802"), now).unwrap().bin, "5%");
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"560003902"), now), None);
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"151003802"), now).unwrap().bin, "reject");
+    assert_eq!(binning.counts(), vec![("1%".to_owned(), 1), ("5%".to_owned(), 1), ("reject".to_owned(), 1)]);
+    // Other functions are ignored.
+    assert_eq!(binning.on_reading(&Reading::parse_frame(b"04700;80:"), now), None);
+  }
+}
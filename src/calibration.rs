@@ -0,0 +1,181 @@
+use std::{fmt, fs, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::reading::{self, Reading};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Coupling {
+  Ac,
+  Dc,
+}
+
+/// Correction of the readings of a function. corrected = normalized * gain + offset.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CalibrationEntry {
+  pub function: es51986::Function,
+  /// Applies to any range if omitted.
+  #[serde(default)]
+  pub range: Option<es51986::Range>,
+  /// Applies to both AC and DC if omitted.
+  #[serde(default)]
+  pub coupling: Option<Coupling>,
+  /// Offset in the base unit.
+  #[serde(default, deserialize_with = "reading::deserialize_quantity")]
+  pub offset: f64,
+  #[serde(default = "gain_default")]
+  pub gain: f64,
+}
+
+fn gain_default() -> f64 {
+  1.0
+}
+
+impl CalibrationEntry {
+  fn matches(&self, reading: &Reading) -> bool {
+    let data = &reading.raw;
+    let coupling = match &self.coupling {
+      None => true,
+      Some(Coupling::Ac) => data.option2.is_ac,
+      Some(Coupling::Dc) => data.option2.is_dc,
+    };
+    self.function == data.function && self.range.as_ref().map(|r| r == &data.range).unwrap_or(true) && coupling
+  }
+}
+
+/// The correction applied to a reading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+  /// Corrected value in the base unit.
+  pub value: f64,
+  pub offset: f64,
+  pub gain: f64,
+  /// SHA-256 of the calibration file.
+  pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalibrationErr {
+  CannotRead { path: PathBuf, msg: String },
+  InvalidFormat { path: PathBuf, msg: String },
+}
+
+impl fmt::Display for CalibrationErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CalibrationErr::CannotRead { path, msg } => write!(f, "Cannot read calibration file '{}': {}", path.display(), msg),
+      CalibrationErr::InvalidFormat { path, msg } => write!(f, "Invalid calibration file '{}': {}", path.display(), msg),
+    }
+  }
+}
+
+/// Calibration table. The first entry matching the function, range and AC/DC of a reading is applied.
+///
+/// ```toml
+/// [[calibration]]
+/// function = "Voltage"
+/// range = "Range1"
+/// coupling = "dc"
+/// gain = 1.0012
+/// offset = "-0.3m"
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationTable {
+  entries: Vec<CalibrationEntry>,
+  hash: String,
+}
+
+impl CalibrationTable {
+  pub fn parse(text: &str, path: &Path) -> Result<Self, CalibrationErr> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct File {
+      #[serde(default)]
+      calibration: Vec<CalibrationEntry>,
+    }
+    let file: File = toml::from_str(text).map_err(|e| CalibrationErr::InvalidFormat { path: path.to_owned(), msg: e.to_string() })?;
+    let hash = Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(Self { entries: file.calibration, hash })
+  }
+
+  pub fn load(path: &Path) -> Result<Self, CalibrationErr> {
+    let text = fs::read_to_string(path).map_err(|e| CalibrationErr::CannotRead { path: path.to_owned(), msg: e.to_string() })?;
+    Self::parse(&text, path)
+  }
+
+  pub fn hash(&self) -> &str {
+    &self.hash
+  }
+
+  /// Fill the calibration field of the reading. The readings without value are not corrected.
+  pub fn apply(&self, reading: &mut Reading) {
+    reading.calibration = None;
+    let Some(value) = reading.measured() else { return };
+    if let Some(entry) = self.entries.iter().find(|e| e.matches(reading)) {
+      reading.calibration = Some(Calibration {
+        value: value * entry.gain + entry.offset,
+        offset: entry.offset,
+        gain: entry.gain,
+        hash: self.hash.clone(),
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use crate::reading::Reading;
+  use super::CalibrationTable;
+
+  const TABLE: &str = r#"
+[[calibration]]
+function = "Voltage"
+coupling = "dc"
+offset = "-2m"
+
+[[calibration]]
+function = "Ohm"
+range = "Range1"
+gain = 1.01
+"#;
+
+  #[test]
+  fn unknown_key() {
+    let err = CalibrationTable::parse("[[calibration]]\nfunction = \"Voltage\"\nofset = 0.01\n", Path::new("cal.toml")).err().unwrap();
+    assert!(err.to_string().contains("ofset"), "{}", err);
+    let err = CalibrationTable::parse("[[calibrations]]\nfunction = \"Voltage\"\n", Path::new("cal.toml")).err().unwrap();
+    assert!(err.to_string().contains("calibrations"), "{}", err);
+  }
+
+  #[test]
+  fn apply() {
+    let table = CalibrationTable::parse(TABLE, Path::new("cal.toml")).unwrap();
+    assert_eq!(table.hash().len(), 64);
+
+    let mut r = Reading::parse_frame(b"110003802");
+    table.apply(&mut r);
+    assert!((r.normalized().unwrap() - 1010.0).abs() < 1e-9);
+    assert_eq!(r.measured(), Some(1000.0));
+    assert_eq!(r.calibration.as_ref().unwrap().hash, table.hash());
+
+    let mut r = Reading::parse_frame(b"01234;<0:");
+    table.apply(&mut r);
+    assert!((r.normalized().unwrap() + 1.236).abs() < 1e-9);
+
+    let mut r = Reading::parse_frame(b"560003902");
+    table.apply(&mut r);
+    assert_eq!(r.calibration, None);
+
+    // No entry for the range.
+    let mut r = Reading::parse_frame(b"009853802");
+    table.apply(&mut r);
+    assert_eq!(r.calibration, None);
+  }
+
+  #[test]
+  fn invalid() {
+    assert!(CalibrationTable::parse("[[calibration]]\nfunction = \"Foo\"", Path::new("cal.toml")).is_err());
+  }
+}
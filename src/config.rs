@@ -0,0 +1,529 @@
+use std::{collections::HashMap, fmt, fs, path::{Path, PathBuf}};
+use serde::{Deserialize, Deserializer};
+use m6000m_rs::{alarm::AlarmRule, announcement::Locale, reading, recording::RecordingConfig, scaling::ScaleRule, smoothing::SmoothingConfig, speech::SpeechConfig, stable::StableConfig, tone::ToneConfig, tts::{Speaker, TtsConfig, VoiceTuning}};
+use crate::{arg::{Args, BinningSettings, OutputFormat}, output::OutputConfig};
+
+/// Settings that can be written in the configuration file. Each key has the same name as the command line option.
+/// Unknown keys are rejected so that a misspelled key does not go unnoticed.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+  pub port: Option<String>,
+  pub output_format: Option<OutputFormat>,
+  pub voicebox_url: Option<String>,
+  pub voicebox_speaker: Option<Speaker>,
+  pub voice_locale: Option<Locale>,
+  pub voice_template: Option<String>,
+  pub audio_output_device_name: Option<String>,
+  pub outputs: Option<Vec<OutputConfig>>,
+  pub stats: Option<bool>,
+  pub stats_interval: Option<u64>,
+  #[serde(deserialize_with = "reading::deserialize_optional_quantity")]
+  pub rel: Option<f64>,
+  pub alarms: Option<Vec<AlarmRule>>,
+  pub binning: Option<BinningSettings>,
+  pub capture: Option<CaptureSetting>,
+  pub smoothing: Option<SmoothingConfig>,
+  pub calibration: Option<PathBuf>,
+  pub scales: Option<Vec<ScaleRule>>,
+  pub tts: Option<TtsConfig>,
+  pub tts_cache: Option<PathBuf>,
+  pub speech: Option<SpeechConfig>,
+  pub voice_tuning: Option<VoiceTuning>,
+  pub tone: Option<ToneConfig>,
+  pub speech_recording: Option<RecordingConfig>,
+  #[serde(deserialize_with = "reading::deserialize_optional_quantity")]
+  pub deadband: Option<f64>,
+  pub deadband_percent: Option<f64>,
+  pub heartbeat: Option<f64>,
+  pub aggregate: Option<f64>,
+}
+
+/// `capture = true` turns on the capture mode with the default conditions. A table gives the conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureSetting {
+  Enabled(bool),
+  Conditions(StableConfig),
+}
+
+impl<'de> Deserialize<'de> for CaptureSetting {
+  // Not untagged so that a misspelled key of the conditions is reported instead of "did not match any variant".
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    match toml::Value::deserialize(d)? {
+      toml::Value::Boolean(enabled) => Ok(CaptureSetting::Enabled(enabled)),
+      value => StableConfig::deserialize(value).map(CaptureSetting::Conditions).map_err(serde::de::Error::custom),
+    }
+  }
+}
+
+impl Settings {
+  /// Fill the fields not set in self with the ones in lower.
+  pub fn or(self, lower: Settings) -> Settings {
+    Settings {
+      port: self.port.or(lower.port),
+      output_format: self.output_format.or(lower.output_format),
+      voicebox_url: self.voicebox_url.or(lower.voicebox_url),
+      voicebox_speaker: self.voicebox_speaker.or(lower.voicebox_speaker),
+      voice_locale: self.voice_locale.or(lower.voice_locale),
+      voice_template: self.voice_template.or(lower.voice_template),
+      audio_output_device_name: self.audio_output_device_name.or(lower.audio_output_device_name),
+      outputs: self.outputs.or(lower.outputs),
+      stats: self.stats.or(lower.stats),
+      stats_interval: self.stats_interval.or(lower.stats_interval),
+      rel: self.rel.or(lower.rel),
+      alarms: self.alarms.or(lower.alarms),
+      binning: self.binning.or(lower.binning),
+      capture: self.capture.or(lower.capture),
+      smoothing: self.smoothing.or(lower.smoothing),
+      calibration: self.calibration.or(lower.calibration),
+      scales: self.scales.or(lower.scales),
+      tts: self.tts.or(lower.tts),
+      tts_cache: self.tts_cache.or(lower.tts_cache),
+      speech: self.speech.or(lower.speech),
+      voice_tuning: self.voice_tuning.or(lower.voice_tuning),
+      tone: self.tone.or(lower.tone),
+      speech_recording: self.speech_recording.or(lower.speech_recording),
+      deadband: self.deadband.or(lower.deadband),
+      deadband_percent: self.deadband_percent.or(lower.deadband_percent),
+      heartbeat: self.heartbeat.or(lower.heartbeat),
+      aggregate: self.aggregate.or(lower.aggregate),
+    }
+  }
+}
+
+/// Contents of the configuration file.
+///
+/// ```toml
+/// port = "/dev/ttyUSB0"
+///
+/// [profiles.bench-voltage]
+/// voicebox-url = "http://localhost:50021"
+/// voicebox-speaker = 9
+///
+/// [[profiles.bench-voltage.outputs]]
+/// name = "log"
+/// type = "file"
+/// path = "bench.csv"
+/// format = "csv"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+  pub settings: Settings,
+  pub profiles: HashMap<String, Settings>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigErr {
+  CannotRead { path: PathBuf, msg: String },
+  InvalidFormat { path: PathBuf, msg: String },
+  NoConfigFile { profile: String },
+  UnknownProfile { profile: String, path: PathBuf },
+}
+
+impl fmt::Display for ConfigErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigErr::CannotRead { path, msg } => write!(f, "Cannot read '{}': {}", path.display(), msg),
+      ConfigErr::InvalidFormat { path, msg } => write!(f, "Invalid configuration file '{}': {}", path.display(), msg),
+      ConfigErr::NoConfigFile { profile } => write!(f, "Profile '{}' is specified but no configuration file is found.", profile),
+      ConfigErr::UnknownProfile { profile, path } => write!(f, "Profile '{}' is not defined in '{}'.", profile, path.display()),
+    }
+  }
+}
+
+impl Config {
+  pub fn parse(s: &str, path: &Path) -> Result<Config, ConfigErr> {
+    let invalid = |msg: String| ConfigErr::InvalidFormat { path: path.to_owned(), msg };
+    // The top level settings are not flattened with serde, which cannot reject unknown keys of a flattened struct.
+    let mut table: toml::Table = toml::from_str(s).map_err(|e| invalid(e.to_string()))?;
+    let profiles = match table.remove("profiles") {
+      Some(profiles) => profiles.try_into().map_err(|e: toml::de::Error| invalid(format!("profiles: {}", e)))?,
+      None => HashMap::new(),
+    };
+    let settings = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| invalid(e.to_string()))?;
+    Ok(Config { settings, profiles })
+  }
+
+  pub fn load(path: &Path) -> Result<Config, ConfigErr> {
+    let s = fs::read_to_string(path).map_err(|e| ConfigErr::CannotRead { path: path.to_owned(), msg: e.to_string() })?;
+    Self::parse(&s, path)
+  }
+
+  /// Settings of the specified profile. The top level settings are used for the keys the profile does not have.
+  pub fn settings(&self, profile: Option<&str>, path: &Path) -> Result<Settings, ConfigErr> {
+    match profile {
+      None => Ok(self.settings.clone()),
+      Some(profile) => match self.profiles.get(profile) {
+        Some(s) => Ok(s.clone().or(self.settings.clone())),
+        None => Err(ConfigErr::UnknownProfile { profile: profile.to_owned(), path: path.to_owned() }),
+      }
+    }
+  }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+  dirs::config_dir().map(|d| d.join("m6000m").join("config.toml"))
+}
+
+impl Args {
+  /// Apply the settings. Options specified in the command line take precedence.
+  pub fn apply(mut self, settings: Settings) -> Args {
+    let cli = Settings {
+      port: self.port.take(),
+      output_format: self.output_format.take(),
+      voicebox_url: self.voicebox_url.take(),
+      voicebox_speaker: self.voicebox_speaker.take(),
+      voice_locale: self.voice_locale.take(),
+      voice_template: self.voice_template.take(),
+      audio_output_device_name: self.audio_output_device_name.take(),
+      outputs: self.outputs.take(),
+      stats: if self.stats { Some(true) } else { None },
+      stats_interval: self.stats_interval.take(),
+      rel: self.rel.take(),
+      alarms: self.alarms.take(),
+      binning: self.binning.take(),
+      capture: self.capture_config.take().map(CaptureSetting::Conditions),
+      smoothing: self.smoothing_config.take(),
+      calibration: self.calibration.take(),
+      scales: self.scales.take(),
+      tts: self.tts_config.take(),
+      tts_cache: self.tts_cache.take(),
+      speech: self.speech_config.take(),
+      voice_tuning: self.voice_tuning.take(),
+      tone: self.tone_config.take(),
+      speech_recording: self.speech_recording.take(),
+      deadband: self.deadband.take(),
+      deadband_percent: self.deadband_percent.take(),
+      heartbeat: self.heartbeat.take(),
+      aggregate: self.aggregate.take(),
+    }.or(settings);
+    self.port = cli.port;
+    self.output_format = cli.output_format;
+    self.voicebox_url = cli.voicebox_url;
+    self.voicebox_speaker = cli.voicebox_speaker;
+    self.voice_locale = cli.voice_locale;
+    self.voice_template = cli.voice_template;
+    self.audio_output_device_name = cli.audio_output_device_name;
+    self.outputs = cli.outputs;
+    self.stats = cli.stats.unwrap_or(false);
+    self.stats_interval = cli.stats_interval;
+    self.rel = cli.rel;
+    self.alarms = cli.alarms;
+    self.binning = cli.binning;
+    match cli.capture {
+      Some(CaptureSetting::Enabled(enabled)) => self.capture |= enabled,
+      Some(CaptureSetting::Conditions(c)) => self.capture_config = Some(c),
+      None => {}
+    }
+    self.smoothing_config = cli.smoothing;
+    self.calibration = cli.calibration;
+    self.scales = cli.scales;
+    self.tts_config = cli.tts;
+    self.tts_cache = cli.tts_cache;
+    self.speech_config = cli.speech;
+    self.voice_tuning = cli.voice_tuning;
+    self.tone_config = cli.tone;
+    self.speech_recording = cli.speech_recording;
+    self.deadband = cli.deadband;
+    self.deadband_percent = cli.deadband_percent;
+    self.heartbeat = cli.heartbeat;
+    self.aggregate = cli.aggregate;
+    self
+  }
+}
+
+/// Merge the configuration file into the command line arguments.
+pub fn resolve(args: Args) -> Result<Args, ConfigErr> {
+  let path: Option<PathBuf> = match &args.config {
+    Some(path) => Some(path.clone()),
+    None => default_path().filter(|p| p.exists()),
+  };
+  match path {
+    Some(path) => {
+      let settings = Config::load(&path)?.settings(args.profile.as_deref(), &path)?;
+      Ok(args.apply(settings))
+    }
+    None => match &args.profile {
+      Some(profile) => Err(ConfigErr::NoConfigFile { profile: profile.clone() }),
+      None => Ok(args),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use m6000m_rs::{audio::Sound, filter::DeadbandConfig, recording::RecordingConfig, scaling::Mapping, smoothing::{SmoothingConfig, SmoothingMethod}, speech::{SpeechConfig, SpeechPolicy}, stable::StableConfig, tone::ToneConfig, tts::{Speaker, TtsConfig, VoiceTuning}};
+  use crate::arg::{Args, OutputFormat, SpeakOn, ToneMode};
+  use super::{Config, ConfigErr};
+
+  const CONFIG: &str = r#"
+port = "/dev/ttyUSB0"
+voicebox-speaker = 3
+
+[profiles.bench-voltage]
+voicebox-url = "http://localhost:50021"
+voicebox-speaker = 9
+
+[profiles.soak-test]
+port = "/dev/ttyUSB1"
+output-format = "jsonl"
+"#;
+
+  #[test]
+  fn profile_overrides_top_level() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(CONFIG, path).unwrap();
+
+    let s = config.settings(None, path).unwrap();
+    assert_eq!(s.port, Some("/dev/ttyUSB0".to_owned()));
+    assert_eq!(s.voicebox_speaker, Some(Speaker::Id(3)));
+    assert_eq!(s.voicebox_url, None);
+
+    let s = config.settings(Some("bench-voltage"), path).unwrap();
+    assert_eq!(s.port, Some("/dev/ttyUSB0".to_owned()));
+    assert_eq!(s.voicebox_speaker, Some(Speaker::Id(9)));
+    assert_eq!(s.voicebox_url, Some("http://localhost:50021".to_owned()));
+
+    let s = config.settings(Some("soak-test"), path).unwrap();
+    assert_eq!(s.port, Some("/dev/ttyUSB1".to_owned()));
+    assert_eq!(s.output_format, Some(OutputFormat::Jsonl));
+  }
+
+  #[test]
+  fn unknown_profile() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(CONFIG, path).unwrap();
+    assert_eq!(
+      config.settings(Some("foo"), path).err().unwrap(),
+      ConfigErr::UnknownProfile { profile: "foo".to_owned(), path: path.to_owned() }
+    );
+  }
+
+  #[test]
+  fn deadband() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+deadband = "10m"
+heartbeat = 30
+rel = "4.7k"
+
+[profiles.fine]
+deadband-percent = 0.1
+"#, path).unwrap();
+    let args = Args { heartbeat: Some(5.0), ..Default::default() }.apply(config.settings(Some("fine"), path).unwrap());
+    assert_eq!(args.deadband(), Some(DeadbandConfig { absolute: 0.01, relative: 0.1, heartbeat: Some(5.0) }));
+    assert_eq!(args.rel, Some(4700.0));
+  }
+
+  #[test]
+  fn aggregate() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[profiles.soak-test]
+aggregate = 60
+"#, path).unwrap();
+    assert_eq!(Args::default().apply(config.settings(Some("soak-test"), path).unwrap()).aggregate, Some(60.0));
+    let args = Args { aggregate: Some(1.0), ..Default::default() }.apply(config.settings(Some("soak-test"), path).unwrap());
+    assert_eq!(args.aggregate, Some(1.0));
+  }
+
+  #[test]
+  fn unknown_key() {
+    let path = Path::new("config.toml");
+    let err = Config::parse("voicebox-speeker = 3\n", path).err().unwrap();
+    assert!(matches!(&err, ConfigErr::InvalidFormat { msg, .. } if msg.contains("voicebox-speeker")), "{}", err);
+    let err = Config::parse("[profiles.bench]\nprot = \"/dev/ttyUSB0\"\n", path).err().unwrap();
+    assert!(matches!(&err, ConfigErr::InvalidFormat { msg, .. } if msg.contains("prot")), "{}", err);
+  }
+
+  #[test]
+  fn unknown_nested_key() {
+    let path = Path::new("config.toml");
+    for (toml, key) in [
+      ("[[outputs]]\nname = \"o\"\ntype = \"stdout\"\nenabeld = false\n", "enabeld"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"file\"\npath = \"m.csv\"\napend = true\n", "apend"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"stdout\"\nfilter = { skip_overflow = true }\n", "skip_overflow"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"stdout\"\nfilter = { deadband = { absolut = 1 } }\n", "absolut"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"stdout\"\nfilter = { aggregate = { windw = 60 } }\n", "windw"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"stdout\"\nqueue = { capacty = 8 }\n", "capacty"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"speech\"\nengine = \"espeak-ng\"\nvoise = \"en-us\"\n", "voise"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"speech\"\nengine = \"espeak-ng\"\nlocal = \"en\"\n", "local"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"speech\"\nengine = \"espeak-ng\"\nrecording = { directry = \"wav\" }\n", "directry"),
+      ("[[outputs]]\nname = \"o\"\ntype = \"tone\"\nmode = \"continuity\"\ntreshold = 10\n", "treshold"),
+      ("[[alarms]]\nname = \"a\"\ntype = \"above\"\nlimit = 5\nmin_duration = 2\n", "min_duration"),
+      ("capture = { tolerence = 0.2 }\n", "tolerence"),
+      ("[smoothing]\nmethod = \"ema\"\nalpah = 0.1\n", "alpah"),
+      ("[binning]\nnominal = 100\nbins = []\nlogg = \"parts.csv\"\n", "logg"),
+      ("[binning]\nnominal = 100\nbins = [{ name = \"1%\", tolerence = 1 }]\n", "tolerence"),
+      ("[binning]\nnominal = 100\nbins = []\nstable = { duraton = 1 }\n", "duraton"),
+      ("[[scales]]\nname = \"s\"\nunit = \"A\"\ntype = \"linear\"\ngian = 100\n", "gian"),
+      ("speech = { on = \"stable\", tolerence = 0.2 }\n", "tolerence"),
+      ("speech = { on = \"change\", deadbnd = 1 }\n", "deadbnd"),
+      ("speech = { on = \"demand\", repeet = true }\n", "repeet"),
+      ("tts = { engine = \"voicevox\", url = \"http://localhost:50021\", sped = 1.5 }\n", "sped"),
+      ("voice-tuning = { sped = 1.5 }\n", "sped"),
+      ("tone = { mode = \"pitch\", hihg = 5 }\n", "hihg"),
+      ("speech-recording = { plaback = false }\n", "plaback"),
+    ] {
+      let err = Config::parse(toml, path).err().unwrap_or_else(|| panic!("'{}' is accepted", key));
+      assert!(matches!(&err, ConfigErr::InvalidFormat { msg, .. } if msg.contains(key)), "{}", err);
+    }
+  }
+
+  #[test]
+  fn binning() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[binning]
+nominal = "4.7k"
+function = "Ohm"
+bins = [{ name = "1%", tolerance = 1.0 }]
+sound = "speak"
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    let binning = args.binning().unwrap();
+    assert_eq!(binning.binning.nominal, 4700.0);
+    assert_eq!(binning.sound, Sound::Speak);
+    assert_eq!(binning.log, None);
+
+    let args = Args { bin_nominal: Some(100.0), ..args };
+    let binning = args.binning().unwrap();
+    assert_eq!(binning.binning.nominal, 100.0);
+    assert_eq!(binning.binning.bins.iter().map(|b| b.name.as_str()).collect::<Vec<_>>(), vec!["1%", "5%"]);
+  }
+
+  #[test]
+  fn capture() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+capture = true
+
+[profiles.fine]
+capture = { tolerance = 0.1 }
+
+[profiles.off]
+capture = false
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    assert_eq!(args.capture(), Some(StableConfig::default()));
+    let args = Args { capture_duration: Some(2.0), ..Args::default() }.apply(config.settings(Some("fine"), path).unwrap());
+    assert_eq!(args.capture(), Some(StableConfig { tolerance: 0.1, duration: 2.0, ..Default::default() }));
+    assert_eq!(Args::default().apply(config.settings(Some("off"), path).unwrap()).capture(), None);
+    let args = Args { capture: true, ..Args::default() }.apply(config.settings(Some("off"), path).unwrap());
+    assert_eq!(args.capture(), Some(StableConfig::default()));
+  }
+
+  #[test]
+  fn smoothing() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[smoothing]
+method = "median"
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    assert_eq!(args.smoothing(), Some(SmoothingConfig { method: SmoothingMethod::Median, length: 5, alpha: 0.2 }));
+
+    let args = Args { smooth: Some(SmoothingMethod::Ema), smooth_alpha: Some(0.5), ..args };
+    assert_eq!(args.smoothing(), Some(SmoothingConfig { method: SmoothingMethod::Ema, length: 5, alpha: 0.5 }));
+    assert_eq!(Args::default().smoothing(), None);
+  }
+
+  #[test]
+  fn scales() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[scales]]
+name = "clamp"
+function = "Voltage"
+unit = "A"
+type = "linear"
+gain = 100.0
+
+[[scales]]
+name = "thermocouple"
+unit = "°C"
+type = "table"
+points = [[0.0, 0.0], [1.0, 100.0]]
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    let scales = args.scales.unwrap();
+    assert_eq!(scales[0].mapping, Mapping::Linear { gain: 100.0, offset: 0.0 });
+    assert_eq!(scales[1].mapping, Mapping::Table { points: vec![[0.0, 0.0], [1.0, 100.0]] });
+
+    let err = Config::parse("[[scales]]\nname = \"empty\"\nunit = \"A\"\ntype = \"table\"\npoints = []\n", path).err().unwrap();
+    assert!(matches!(&err, ConfigErr::InvalidFormat { msg, .. } if msg.contains("at least one point")), "{}", err);
+  }
+
+  #[test]
+  fn speech() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+speech = { on = "stable", tolerance = 0.2, repeat = true }
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    let speech = args.speech();
+    assert_eq!(speech.policy, SpeechPolicy::Stable(StableConfig { tolerance: 0.2, ..Default::default() }));
+    assert!(speech.repeat);
+
+    let stable = Args { speak_on: Some(SpeakOn::Stable), ..args.clone() };
+    assert_eq!(stable.speech().policy, SpeechPolicy::Stable(StableConfig { tolerance: 0.2, ..Default::default() }));
+
+    let args = Args { speak_on: Some(SpeakOn::Interval), speak_interval: Some(2.0), ..args };
+    assert_eq!(args.speech().policy, SpeechPolicy::Interval { seconds: 2.0 });
+    assert_eq!(Args::default().speech(), SpeechConfig::default());
+  }
+
+  #[test]
+  fn speech_recording() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+speech-recording = { directory = "speech", playback = false }
+"#, path).unwrap();
+    let args = Args { speech_wav_track: Some("session.wav".into()), ..Default::default() }.apply(config.settings(None, path).unwrap());
+    assert_eq!(args.recording(), RecordingConfig { directory: Some("speech".into()), track: Some("session.wav".into()), playback: false });
+    assert_eq!(Args::default().recording(), RecordingConfig::default());
+  }
+
+  #[test]
+  fn tone() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+tone = { mode = "continuity", threshold = "50", frequency = 1000 }
+"#, path).unwrap();
+    let args = Args::default().apply(config.settings(None, path).unwrap());
+    assert_eq!(args.tone(), Some(ToneConfig::Continuity { threshold: 50.0, frequency: 1000.0 }));
+
+    let args = Args { tone_threshold: Some(5.0), ..args };
+    assert_eq!(args.tone(), Some(ToneConfig::Continuity { threshold: 5.0, frequency: 1000.0 }));
+    let args = Args { tone: Some(ToneMode::Pitch), tone_high: Some(1e3), ..args };
+    assert_eq!(args.tone(), Some(ToneConfig::pitch(None, Some(1e3))));
+    assert_eq!(Args::default().tone(), None);
+  }
+
+  #[test]
+  fn voice_tuning() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+voice-tuning = { speed = 1.2, volume = 0.8 }
+tts = { engine = "voicevox", url = "http://localhost:50021", speed = 1.5, pitch = 0.1 }
+"#, path).unwrap();
+    let args = Args { voice_pitch: Some(-0.1), ..Default::default() }.apply(config.settings(None, path).unwrap());
+    match args.tts().unwrap() {
+      TtsConfig::Voicevox { tuning, .. } => {
+        assert_eq!(tuning, VoiceTuning { speed: Some(1.5), pitch: Some(-0.1), volume: Some(0.8), ..Default::default() });
+      }
+      tts => panic!("Unexpected engine {:?}", tts),
+    }
+  }
+
+  #[test]
+  fn command_line_takes_precedence() {
+    let path = Path::new("config.toml");
+    let settings = Config::parse(CONFIG, path).unwrap().settings(Some("bench-voltage"), path).unwrap();
+    let args = Args { voicebox_speaker: Some("ずんだもん".parse().unwrap()), ..Default::default() }.apply(settings);
+    assert_eq!(args.port, Some("/dev/ttyUSB0".to_owned()));
+    assert_eq!(args.voicebox_speaker, Some(Speaker::Name { name: "ずんだもん".to_owned(), style: None }));
+    assert_eq!(args.voicebox_url, Some("http://localhost:50021".to_owned()));
+  }
+}
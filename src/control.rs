@@ -0,0 +1,50 @@
+use std::io::{self, BufRead, IsTerminal};
+use log::info;
+use tokio::sync::mpsc;
+
+/// Commands typed on the terminal while measuring.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Statistics,
+    /// Use the latest reading as the reference of REL mode.
+    CaptureReference,
+    /// Leave REL mode.
+    ClearReference,
+    /// Speak the next reading.
+    Speak,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Option<Command> {
+        match line.trim() {
+            "s" => Some(Command::Statistics),
+            "r" => Some(Command::CaptureReference),
+            "n" => Some(Command::ClearReference),
+            "v" => Some(Command::Speak),
+            _ => None,
+        }
+    }
+
+    pub fn help() -> &'static str {
+        "Commands: s) statistics, r) REL mode with the latest reading as reference, n) leave REL mode, v) speak the next reading"
+    }
+}
+
+/// Read the commands from stdin. Nothing is read if stdin is not a terminal.
+pub fn launch_stdin_reader() -> mpsc::UnboundedReceiver<Command> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if io::stdin().is_terminal() {
+        eprintln!("{}", Command::help());
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                match Command::parse(&line) {
+                    Some(cmd) => if tx.send(cmd).is_err() { break },
+                    None => eprintln!("Unknown command '{}'. {}", line.trim(), Command::help()),
+                }
+            }
+            info!("Stop reading commands.");
+        });
+    }
+    rx
+}
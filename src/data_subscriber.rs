@@ -1,43 +1,189 @@
-use std::{fs::File, io::{self, BufReader, Cursor, Read, Write}, sync::mpsc::{self, TryRecvError}, thread};
-use rodio::Source;
-use serde_jsonlines::WriteExt;
-use crate::{arg, format::Jsonl};
-use log::{error, warn, info, debug};
+use std::{fs::{File, OpenOptions}, io::{self, BufWriter, Write}, net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket}, path::PathBuf, sync::mpsc::{self, TryRecvError}, thread, time::{Instant, SystemTime}};
+use crate::{announcement::Announcer, audio, format::OutputFormat, reading::Reading, recording::SpeechRecorder, speech::SpeechScheduler, tone::{ToneConfig, ToneControl}, tts::TtsBackend};
+use log::{error, warn, info};
 
-pub trait DataSubscriber {
+pub trait DataSubscriber: Send {
   /// Implement this method to handle received data.
-  fn on_data(&mut self, data: &es51986::Output);
+  fn on_data(&mut self, data: &Reading);
+
+  /// Called when no more data comes.
+  fn on_close(&mut self) {}
 }
 
 /// A DataSubscriber that reports data to stdout.
 pub struct StdoutDataSubscriber {
-  format: arg::OutputFormat,
+  format: OutputFormat,
+  header_written: bool,
 }
 
 impl StdoutDataSubscriber {
-  pub fn new(format: arg::OutputFormat) -> Self {
-    Self { format }
+  pub fn new(format: OutputFormat) -> Self {
+    Self { format, header_written: false }
   }
 }
 
 impl DataSubscriber for StdoutDataSubscriber {
-  fn on_data(&mut self, data: &es51986::Output) {
-    match self.format {
-        arg::OutputFormat::Jsonl => {
-            let json = Jsonl {
-                value: data.get_value(),
-                raw: data.clone(),
-            };
-            io::stdout().write_json_lines(vec![json]).unwrap();
+  fn on_data(&mut self, data: &Reading) {
+    let mut stdout = io::stdout().lock();
+    if let Err(err) = write_record(&mut stdout, &self.format, data, &mut self.header_written) {
+      error!("Cannot write to stdout: {:?}", err);
+    }
+  }
+}
+
+fn write_record<W: Write>(w: &mut W, format: &OutputFormat, data: &Reading, header_written: &mut bool) -> io::Result<()> {
+  if !*header_written {
+    if let Some(header) = format.header() {
+      writeln!(w, "{}", header)?;
+    }
+    *header_written = true;
+  }
+  writeln!(w, "{}", format.format(data))?;
+  w.flush()
+}
+
+/// A DataSubscriber that writes data to a file.
+pub struct FileDataSubscriber {
+  format: OutputFormat,
+  path: PathBuf,
+  file: BufWriter<File>,
+  header_written: bool,
+}
+
+impl FileDataSubscriber {
+  pub fn new(format: OutputFormat, path: PathBuf, append: bool) -> io::Result<Self> {
+    let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(&path)?;
+    let header_written = append && 0 < file.metadata()?.len();
+    Ok(Self { format, path, file: BufWriter::new(file), header_written })
+  }
+}
+
+impl DataSubscriber for FileDataSubscriber {
+  fn on_data(&mut self, data: &Reading) {
+    if let Err(err) = write_record(&mut self.file, &self.format, data, &mut self.header_written) {
+      error!("Cannot write to {}: {:?}", self.path.display(), err);
+    }
+  }
+}
+
+/// A DataSubscriber that sends data to a TCP server. The connection is made when the data arrives
+/// and is made again after an error.
+pub struct TcpDataSubscriber {
+  format: OutputFormat,
+  address: String,
+  stream: Option<TcpStream>,
+  header_written: bool,
+}
+
+impl TcpDataSubscriber {
+  pub fn new(format: OutputFormat, address: String) -> Self {
+    Self { format, address, stream: None, header_written: false }
+  }
+}
+
+impl DataSubscriber for TcpDataSubscriber {
+  fn on_data(&mut self, data: &Reading) {
+    if self.stream.is_none() {
+      match TcpStream::connect(&self.address) {
+        Ok(stream) => {
+          info!("Connected to {}.", self.address);
+          self.stream = Some(stream);
+          self.header_written = false;
         }
+        Err(err) => {
+          error!("Cannot connect to {}: {:?}", self.address, err);
+          return;
+        }
+      }
     }
+
+    if let Some(stream) = &mut self.stream {
+      if let Err(err) = write_record(stream, &self.format, data, &mut self.header_written) {
+        error!("Cannot send to {}: {:?}", self.address, err);
+        self.stream = None;
+      }
+    }
+  }
+}
+
+/// A DataSubscriber that sends each data as a UDP datagram.
+pub struct UdpDataSubscriber {
+  format: OutputFormat,
+  address: String,
+  socket: UdpSocket,
+}
+
+impl UdpDataSubscriber {
+  pub fn new(format: OutputFormat, address: String) -> io::Result<Self> {
+    let target: SocketAddr = address.to_socket_addrs()?.next().ok_or_else(
+      || io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot resolve {}", address))
+    )?;
+    let socket = if target.is_ipv4() { UdpSocket::bind(("0.0.0.0", 0))? } else { UdpSocket::bind(("::", 0))? };
+    Ok(Self { format, address, socket })
   }
 }
 
+impl DataSubscriber for UdpDataSubscriber {
+  fn on_data(&mut self, data: &Reading) {
+    if let Err(err) = self.socket.send_to(self.format.format(data).as_bytes(), &self.address) {
+      error!("Cannot send to {}: {:?}", self.address, err);
+    }
+  }
+}
+
+/// Tracks whether the voice works. Only the changes of the status are reported so that a failing engine does not
+/// flood the output.
+#[derive(Debug, Clone)]
+pub struct VoiceStatus {
+  failures: usize,
+}
+
+impl Default for VoiceStatus {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl VoiceStatus {
+  pub fn new() -> Self {
+    Self { failures: 0 }
+  }
+
+  /// Update the status with the result of speaking. Returns the status line in JSON when the status changed.
+  pub fn update(&mut self, result: &Result<(), String>) -> Option<String> {
+    match result {
+      Err(msg) => {
+        self.failures += 1;
+        (self.failures == 1).then(|| serde_json::json!({ "voice": { "status": "error", "message": msg } }).to_string())
+      }
+      Ok(()) if self.failures > 0 => {
+        let line = serde_json::json!({ "voice": { "status": "ok", "failures": self.failures } }).to_string();
+        self.failures = 0;
+        Some(line)
+      }
+      Ok(()) => None,
+    }
+  }
+
+  /// Update the status and print the status line to stderr when it changed.
+  pub fn report(&mut self, result: &Result<(), String>) {
+    if let Err(msg) = result {
+      error!("{}", msg);
+    }
+    if let Some(line) = self.update(result) {
+      eprintln!("{}", line);
+    }
+  }
+}
+
+/// A DataSubscriber that speaks the readings chosen by the scheduler with a text-to-speech backend. Readings arriving
+/// while speaking are collapsed into the latest one.
 pub struct VoiceboxDataSubscriber {
-  url: String,
-  speaker: usize,
   tx: mpsc::Sender<String>,
+  announcer: Announcer,
+  scheduler: SpeechScheduler,
+  /// Status of handing the text to the speaking thread, which is gone if it panicked.
+  status: VoiceStatus,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -62,113 +208,27 @@ impl VoiceboxDataSubscriber {
     }
   }
   
-  fn speak(base_url: &str, speaker: usize, msg: String, device: &rodio::Device) {
-    let url: reqwest::Url = match reqwest::Url::parse_with_params(
-      &format!("{}/{}", base_url, "audio_query"),
-      &[
-      ("speaker", speaker.to_string()),
-      ("text", msg.clone()),
-      ]
-    ) {
-        Ok(url) => url,
-        Err(err) => {
-          error!("Parsing url {} failed(speaker = {}, text = {}): {}", err, base_url, speaker, msg);
-          return;
-        }
-    };
-
-    let resp: Result<reqwest::blocking::Response, reqwest::Error> = reqwest::blocking::Client::new()
-    .post(url.clone())
-    .send();
-    
-    let json = match resp {
-        Ok(resp) => {
-          match resp.text() {
-            Ok(json) => json,
-            Err(err) => {
-              error!("Cannot parse response: {:?}", err);
-              return;
-            }
-          }
-        }
-        Err(err) => {
-          error!("Request to url {:?} failed: {:?}", url, err);
-          return;
-        }
+  /// Synthesize the message with the backend, play it on the device and write it with the recorder.
+  pub fn speak(backend: &mut dyn TtsBackend, msg: String, device: Option<&rodio::Device>, recorder: Option<&mut SpeechRecorder>) -> Result<(), String> {
+    let wav = backend.synthesize(&msg).map_err(|err| format!("Cannot speak '{}': {}", msg, err))?;
+    let at = SystemTime::now();
+    let played = match device {
+      Some(device) => audio::play_wav(device, wav.clone()).map_err(|err| err.to_string()),
+      None => Ok(()),
     };
-
-    let url: reqwest::Url = match reqwest::Url::parse_with_params(
-      &format!("{}/{}", base_url, "synthesis"),
-      &[
-      ("speaker", speaker.to_string()),
-      ]
-    ) {
-        Ok(url) => url,
-        Err(err) => {
-          error!("Parsing url {} failed(speaker = {}): {}", err, url, speaker);
-          return;
-        }
-    };
-
-    let resp: Result<reqwest::blocking::Response, reqwest::Error> = reqwest::blocking::Client::new()
-    .post(url.clone())
-    .header("Content-Type", "application/json")
-    .body(json)
-    .send();
-
-    let wav: bytes::Bytes = match resp {
-        Ok(resp) => {
-          match resp.bytes() {
-            Ok(wav) => wav,
-            Err(err) => {
-              error!("Cannot parse response: {:?}", err);
-              return;
-            }
-          }
-        }
-        Err(err) => {
-          error!("Request to url {:?} failed: {:?}", url, err);
-          return;
-        }
-      };
-
-      let wav: Vec<u8> = wav.into();
-      let (_stream, stream_handle) = match rodio::OutputStream::try_from_device(device) {
-        Ok(ok) => ok,
-        Err(err) => {
-          error!("Cannot open device {:?}", err);
-          return;
-        }
-      };
-      let source = match rodio::Decoder::new_wav(Cursor::new(wav)) {
-        Ok(ok) => ok,
-        Err(err) => {
-          error!("Cannot decode wav file: {:?}", err);
-          return;
-        }
-      };
-      let sink = match rodio::Sink::try_new(&stream_handle) {
-        Ok(ok) => ok,
-        Err(err) => {
-          error!("Cannot create sink device {:?}", err);
-          return;
-        }
-      };
-      sink.append(source);
-      sink.sleep_until_end();
+    if let Some(recorder) = recorder {
+      recorder.record(&wav, at).map_err(|err| err.to_string())?;
+    }
+    played
   }
 
-  pub fn new(url: String, speaker: usize, device: rodio::Device) -> Self {
-    let url: String = if url.ends_with("/") {
-      url[..url.len() - 1].to_owned()
-    } else {
-      url
-    };
+  /// The speech is played on the device if any and written with the recorder if any.
+  pub fn new(mut backend: Box<dyn TtsBackend>, device: Option<rodio::Device>, mut recorder: Option<SpeechRecorder>, announcer: Announcer, scheduler: SpeechScheduler) -> Self {
     let (tx, rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel();
-    let cloned_url = url.clone();
+    let mut status = VoiceStatus::new();
     thread::spawn(move || loop {
       match Self::last_msg(&rx) {
-        Ok(msg) => Self::speak(&cloned_url, speaker, msg, &device),
+        Ok(msg) => status.report(&Self::speak(backend.as_mut(), msg, device.as_ref(), recorder.as_mut())),
         Err(VoiceboxDataSubscriberErr::Disconnected) => {
           warn!("Voicebox thread disconnected.");
           break;
@@ -176,30 +236,63 @@ impl VoiceboxDataSubscriber {
       }
     });
 
-    Self { url, tx, speaker }
+    Self { tx, announcer, scheduler, status: VoiceStatus::new() }
   }
 }
 
 impl DataSubscriber for VoiceboxDataSubscriber {
-    fn on_data(&mut self, data: &es51986::Output) {
-      let value: Option<es51986::OutputValue> = data.get_value();
-      if let Some(value) = value {
-        let prefix_unit = match &value.value_unit.prefix_unit {
-            es51986::PrefixUnit::Mega => "メガ",
-            es51986::PrefixUnit::Kilo => "キロ",
-            es51986::PrefixUnit::None => "ナノ",
-            es51986::PrefixUnit::Millis => "ミリ",
-            es51986::PrefixUnit::Micro => "マイクロ",
-            es51986::PrefixUnit::Nano => "ナノ",
-        };
-        let base_unit = match &value.value_unit.base_unit {
-            es51986::BaseUnit::Ampere => "アンペア",
-            es51986::BaseUnit::Volt => "ボルト",
-            es51986::BaseUnit::Ohm => "オーム",
-            es51986::BaseUnit::Hearts => "ヘルツ",
-            es51986::BaseUnit::Farad => "ファラッド",
-        };
-        self.tx.send(format!("{}{}{}", value.digits, prefix_unit, base_unit)).unwrap();
+    fn on_data(&mut self, data: &Reading) {
+      let Some(text) = self.announcer.text(data) else { return };
+      if let Some(text) = self.scheduler.schedule(data, text, Instant::now()) {
+        let result = self.tx.send(text).map_err(|err| format!("Cannot speak '{}': the voice thread stopped.", err.0));
+        if let (Err(msg), Some(line)) = (&result, self.status.update(&result)) {
+          error!("{}", msg);
+          eprintln!("{}", line);
+        }
       }
     }
-}
\ No newline at end of file
+}
+
+/// A DataSubscriber that turns the readings into a continuous tone without speech.
+pub struct ToneDataSubscriber {
+  config: ToneConfig,
+  control: ToneControl,
+}
+
+impl ToneDataSubscriber {
+  pub fn new(device: rodio::Device, config: ToneConfig) -> Self {
+    let control = ToneControl::new();
+    let source = control.source();
+    thread::spawn(move || {
+      if let Err(err) = audio::play(&device, source) {
+        error!("Cannot play the tone: {}", err);
+      }
+    });
+    Self { config, control }
+  }
+}
+
+impl DataSubscriber for ToneDataSubscriber {
+  fn on_data(&mut self, data: &Reading) {
+    self.control.set(self.config.frequency(data), Instant::now());
+  }
+
+  fn on_close(&mut self) {
+    self.control.stop();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn voice_status() {
+    let mut status = VoiceStatus::new();
+    assert_eq!(status.update(&Ok(())), None);
+    assert_eq!(status.update(&Err("down".to_owned())), Some(r#"{"voice":{"message":"down","status":"error"}}"#.to_owned()));
+    assert_eq!(status.update(&Err("down".to_owned())), None);
+    assert_eq!(status.update(&Ok(())), Some(r#"{"voice":{"failures":2,"status":"ok"}}"#.to_owned()));
+    assert_eq!(status.update(&Ok(())), None);
+  }
+}
@@ -0,0 +1,101 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use m6000m_rs::audio;
+use crate::AppErr;
+
+/// Print the audio hosts and their output devices with the supported configurations. The indexes of the default host
+/// can be given to --audio-output-device-name.
+pub fn list_audio_devices() -> Result<(), AppErr> {
+    let default_host = cpal::default_host().id();
+    for host_id in cpal::available_hosts() {
+        let host = cpal::host_from_id(host_id).map_err(|e| AppErr::AudioDeviceError(e.to_string()))?;
+        println!("Host: {}{}", host_id.name(), if host_id == default_host { " (default)" } else { "" });
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("  Cannot list the devices: {}", e);
+                continue;
+            }
+        };
+        for (index, device) in devices.enumerate() {
+            let name = device.name().unwrap_or("Unknown".to_owned());
+            let default = if default_name.as_ref() == Some(&name) { " (default)" } else { "" };
+            println!("  {:>3}  {}{}", index, name, default);
+            if let Ok(config) = device.default_output_config() {
+                println!("         default: {} ch, {} Hz, {}", config.channels(), config.sample_rate().0, config.sample_format());
+            }
+            match device.supported_output_configs() {
+                Ok(configs) => {
+                    for config in configs {
+                        println!("         {} ch, {}-{} Hz, {}", config.channels(), config.min_sample_rate().0, config.max_sample_rate().0, config.sample_format());
+                    }
+                }
+                Err(e) => println!("         Cannot retrieve configuration: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pick the audio output device of the default host and check that it can be opened. The device is chosen by index,
+/// exact name or a unique part of the name (case insensitive).
+pub fn pick_audio_output_device(audio_output_device_name: &Option<String>) -> Result<rodio::Device, AppErr> {
+    let host: cpal::Host = cpal::default_host();
+    let device = match audio_output_device_name {
+        None => host.default_output_device().ok_or(AppErr::AudioDeviceError(
+            "No default audio output device. Run the list-audio-devices command and specify --audio-output-device-name.".to_owned()
+        ))?,
+        Some(query) => {
+            let mut devices: Vec<cpal::Device> = host.output_devices().map_err(|e| AppErr::AudioDeviceError(e.to_string()))?.collect();
+            let names: Vec<String> = devices.iter().map(|d| d.name().unwrap_or_default()).collect();
+            let index = select_device(&names, query).map_err(|msg| {
+                AppErr::AudioDeviceError(format!("{} Run the list-audio-devices command to see the devices.", msg))
+            })?;
+            devices.swap_remove(index)
+        }
+    };
+    audio::check(&device).map_err(|e| AppErr::AudioDeviceError(e.to_string()))?;
+    Ok(device)
+}
+
+/// Index of the device chosen by the query: an index, the exact name or a unique part of the name.
+fn select_device(names: &[String], query: &str) -> Result<usize, String> {
+    if let Ok(index) = query.parse::<usize>() {
+        return if index < names.len() {
+            Ok(index)
+        } else {
+            Err(format!("Device index {} is out of range. {} audio output devices detected.", index, names.len()))
+        };
+    }
+    if let Some(index) = names.iter().position(|name| name == query) {
+        return Ok(index);
+    }
+    let lower = query.to_lowercase();
+    let matched: Vec<usize> = (0..names.len()).filter(|&i| names[i].to_lowercase().contains(&lower)).collect();
+    match matched[..] {
+        [index] => Ok(index),
+        [] => Err(format!("Device name '{}' is not found.", query)),
+        _ => Err(format!(
+            "Device name '{}' matches more than one device: {}.",
+            query,
+            matched.iter().map(|&i| format!("'{}'", names[i])).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::select_device;
+
+    #[test]
+    fn select() {
+        let names: Vec<String> = ["default", "pulse", "hdmi:CARD=PCH,DEV=0", "hdmi:CARD=PCH,DEV=1"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(select_device(&names, "1"), Ok(1));
+        assert!(select_device(&names, "4").is_err());
+        assert_eq!(select_device(&names, "pulse"), Ok(1));
+        assert_eq!(select_device(&names, "PULSE"), Ok(1));
+        assert_eq!(select_device(&names, "dev=1"), Ok(3));
+        assert!(select_device(&names, "hdmi").unwrap_err().contains("more than one"));
+        assert!(select_device(&names, "usb").unwrap_err().contains("not found"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::{data_subscriber::DataSubscriber, reading::{self, Reading, Window}};
+
+/// A stage between the pipeline and a subscriber. A filter can drop, pass or transform the readings.
+pub trait Filter: Send {
+  fn apply(&mut self, reading: Reading, now: Instant) -> Vec<Reading>;
+
+  /// Called when no more readings come. Returns the readings held by the filter.
+  fn flush(&mut self) -> Vec<Reading> {
+    vec![]
+  }
+}
+
+/// A DataSubscriber that passes the readings through the filters.
+pub struct FilteredDataSubscriber {
+  filters: Vec<Box<dyn Filter>>,
+  inner: Box<dyn DataSubscriber>,
+}
+
+impl FilteredDataSubscriber {
+  pub fn new(filters: Vec<Box<dyn Filter>>, inner: Box<dyn DataSubscriber>) -> Self {
+    Self { filters, inner }
+  }
+
+  fn run(&mut self, from: usize, readings: Vec<Reading>, now: Instant) {
+    let mut readings = readings;
+    for f in self.filters[from..].iter_mut() {
+      readings = readings.into_iter().flat_map(|r| f.apply(r, now)).collect();
+    }
+    for r in readings {
+      self.inner.on_data(&r);
+    }
+  }
+}
+
+impl DataSubscriber for FilteredDataSubscriber {
+  fn on_data(&mut self, data: &Reading) {
+    self.run(0, vec![data.clone()], Instant::now());
+  }
+
+  fn on_close(&mut self) {
+    let now = Instant::now();
+    for i in 0..self.filters.len() {
+      let flushed = self.filters[i].flush();
+      self.run(i + 1, flushed, now);
+    }
+    self.inner.on_close();
+  }
+}
+
+/// Deadband. Readings are passed only when the value changes beyond the deadband from the last passed one.
+///
+/// ```toml
+/// deadband = { absolute = "1m", relative = 0.1, heartbeat = 10 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DeadbandConfig {
+  /// Absolute deadband in the base unit.
+  #[serde(deserialize_with = "reading::deserialize_quantity")]
+  pub absolute: f64,
+  /// Relative deadband in percent of the last passed value. The larger one of absolute and relative is used.
+  pub relative: f64,
+  /// Pass a reading at least every this seconds even if the value does not change.
+  pub heartbeat: Option<f64>,
+}
+
+pub struct Deadband {
+  config: DeadbandConfig,
+  last: Option<(Reading, Instant)>,
+}
+
+impl Deadband {
+  pub fn new(config: DeadbandConfig) -> Self {
+    Self { config, last: None }
+  }
+
+  fn changed(&self, last: &Reading, reading: &Reading) -> bool {
+    if last.raw.function != reading.raw.function || last.base_unit() != reading.base_unit() || last.alarms != reading.alarms {
+      return true;
+    }
+    match (last.normalized(), reading.normalized()) {
+      (Some(l), Some(v)) => self.config.absolute.max(l.abs() * self.config.relative / 100.0) < (v - l).abs() || (l == v && last.value != reading.value),
+      (None, None) => last.raw.status.is_overflow != reading.raw.status.is_overflow,
+      _ => true,
+    }
+  }
+}
+
+impl Filter for Deadband {
+  fn apply(&mut self, reading: Reading, now: Instant) -> Vec<Reading> {
+    let pass = match &self.last {
+      None => true,
+      Some((last, at)) => {
+        self.changed(last, &reading) || reading.captured
+          || self.config.heartbeat.map(|h| Duration::from_secs_f64(h.max(0.0)) <= now.duration_since(*at)).unwrap_or(false)
+      }
+    };
+    if pass {
+      self.last = Some((reading.clone(), now));
+      vec![reading]
+    } else {
+      vec![]
+    }
+  }
+}
+
+/// Aggregation into fixed time windows.
+///
+/// ```toml
+/// aggregate = { window = 60 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct AggregateConfig {
+  /// Length of the window in seconds.
+  pub window: f64,
+}
+
+/// Passes a single reading per window: the last reading in the window with the min, max and mean of the window and
+/// every alarm active during the window. A window is closed early when the function or the unit changes, so a window
+/// never mixes the units.
+pub struct Aggregation {
+  window: Duration,
+  start: Option<Instant>,
+  last: Option<Reading>,
+  count: u64,
+  min: f64,
+  max: f64,
+  sum: f64,
+  alarms: Vec<String>,
+}
+
+impl Aggregation {
+  pub fn new(config: AggregateConfig) -> Self {
+    Self { window: Duration::from_secs_f64(config.window.max(0.001)), start: None, last: None, count: 0, min: 0.0, max: 0.0, sum: 0.0, alarms: vec![] }
+  }
+
+  /// Close the window at now.
+  fn take(&mut self, now: Instant) -> Option<Reading> {
+    let mut reading = self.last.take()?;
+    let duration = self.start.map(|start| now.saturating_duration_since(start).min(self.window)).unwrap_or_default();
+    let (min, max, mean) = if self.count == 0 {
+      (None, None, None)
+    } else {
+      (Some(self.min), Some(self.max), Some(self.sum / self.count as f64))
+    };
+    reading.window = Some(Window { duration: duration.as_secs_f64(), count: self.count, min, max, mean });
+    reading.alarms = std::mem::take(&mut self.alarms);
+    self.count = 0;
+    Some(reading)
+  }
+}
+
+impl Filter for Aggregation {
+  fn apply(&mut self, reading: Reading, now: Instant) -> Vec<Reading> {
+    let mut passed = vec![];
+    if let Some(start) = self.start {
+      let elapsed = now.saturating_duration_since(start);
+      let changed = self.last.as_ref()
+        .map(|l| l.raw.function != reading.raw.function || l.base_unit() != reading.base_unit())
+        .unwrap_or(false);
+      if self.window <= elapsed || changed {
+        passed.extend(self.take(now));
+        // Keep the windows aligned unless the window is closed early.
+        self.start = Some(if changed { now } else { start + self.window * (elapsed.as_secs_f64() / self.window.as_secs_f64()) as u32 });
+      }
+    } else {
+      self.start = Some(now);
+    }
+
+    if let Some(v) = reading.normalized() {
+      if self.count == 0 {
+        (self.min, self.max, self.sum) = (v, v, 0.0);
+      }
+      self.count += 1;
+      self.min = self.min.min(v);
+      self.max = self.max.max(v);
+      self.sum += v;
+    }
+    for alarm in &reading.alarms {
+      if !self.alarms.contains(alarm) {
+        self.alarms.push(alarm.clone());
+      }
+    }
+    self.last = Some(reading);
+    passed
+  }
+
+  fn flush(&mut self) -> Vec<Reading> {
+    self.take(Instant::now()).into_iter().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use crate::reading::{Reading, Window};
+  use super::{AggregateConfig, Aggregation, Deadband, DeadbandConfig, Filter};
+
+  fn passed(d: &mut Deadband, b: &[u8], now: Instant) -> bool {
+    !d.apply(Reading::parse_frame(b), now).is_empty()
+  }
+
+  #[test]
+  fn on_change() {
+    let mut d = Deadband::new(DeadbandConfig::default());
+    let now = Instant::now();
+    assert!(passed(&mut d, b"05000;80:", now));
+    assert!(!passed(&mut d, b"05000;80:", now));
+    assert!(passed(&mut d, b"05001;80:", now));
+    assert!(passed(&mut d, b"560003902", now));
+    assert!(!passed(&mut d, b"560003902", now));
+    assert!(passed(&mut d, b"109853802", now));
+  }
+
+  #[test]
+  fn deadband_and_heartbeat() {
+    let mut d = Deadband::new(DeadbandConfig { absolute: 0.005, relative: 0.0, heartbeat: Some(10.0) });
+    let now = Instant::now();
+    assert!(passed(&mut d, b"05000;80:", now));
+    assert!(!passed(&mut d, b"05004;80:", now));
+    assert!(!passed(&mut d, b"04996;80:", now));
+    assert!(passed(&mut d, b"05006;80:", now));
+    // Compared with the last passed value.
+    assert!(!passed(&mut d, b"05002;80:", now));
+    assert!(!passed(&mut d, b"05002;80:", now + Duration::from_secs(9)));
+    assert!(passed(&mut d, b"05002;80:", now + Duration::from_secs(10)));
+
+    let mut d = Deadband::new(DeadbandConfig { absolute: 0.0, relative: 1.0, heartbeat: None });
+    assert!(passed(&mut d, b"110003802", now));
+    assert!(!passed(&mut d, b"110093802", now));
+    assert!(passed(&mut d, b"110113802", now));
+  }
+
+  #[test]
+  fn aggregation() {
+    let mut a = Aggregation::new(AggregateConfig { window: 1.0 });
+    let now = Instant::now();
+    let ms = |n| now + Duration::from_millis(n);
+    assert!(a.apply(Reading::parse_frame(b"05000;80:"), ms(0)).is_empty());
+    let mut alarmed = Reading::parse_frame(b"04900;80:");
+    alarmed.alarms = vec!["rail".to_owned()];
+    assert!(a.apply(alarmed, ms(300)).is_empty());
+    assert!(a.apply(Reading::parse_frame(b"05200;80:"), ms(900)).is_empty());
+
+    let passed = a.apply(Reading::parse_frame(b"05100;80:"), ms(1200));
+    assert_eq!(passed.len(), 1);
+    assert_eq!(passed[0].raw.digits, Reading::parse_frame(b"05200;80:").raw.digits);
+    let w = passed[0].window.clone().unwrap();
+    assert_eq!((w.count, w.min, w.max), (3, Some(4.9), Some(5.2)));
+    assert!((w.mean.unwrap() - 5.0333333).abs() < 1e-6);
+    assert_eq!(w.duration, 1.0);
+    // The alarm active in the middle of the window is kept.
+    assert_eq!(passed[0].alarms, vec!["rail".to_owned()]);
+
+    // Changing the function closes the window early.
+    let passed = a.apply(Reading::parse_frame(b"560003902"), ms(1500));
+    assert_eq!(passed[0].window, Some(Window { duration: 0.5, count: 1, min: Some(5.1), max: Some(5.1), mean: Some(5.1) }));
+    assert!(passed[0].alarms.is_empty());
+
+    // Flushed before any time passed in the window.
+    let passed = a.flush();
+    assert_eq!(passed[0].window, Some(Window { duration: 0.0, count: 0, min: None, max: None, mean: None }));
+    assert!(a.flush().is_empty());
+  }
+}
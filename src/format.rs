@@ -1,7 +1,109 @@
-use serde::{Deserialize, Serialize};
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::{reading::Reading, scaling};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Jsonl {
-  pub raw: es51986::Output,
-  pub value: Option<es51986::OutputValue>,
-}
\ No newline at end of file
+#[derive(ValueEnum, Debug, PartialEq, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+  Jsonl,
+  Csv,
+}
+
+pub fn prefix_symbol(prefix: &es51986::PrefixUnit) -> &'static str {
+  match prefix {
+    es51986::PrefixUnit::Mega => "M",
+    es51986::PrefixUnit::Kilo => "k",
+    es51986::PrefixUnit::None => "",
+    es51986::PrefixUnit::Millis => "m",
+    es51986::PrefixUnit::Micro => "u",
+    es51986::PrefixUnit::Nano => "n",
+  }
+}
+
+pub fn base_unit_symbol(base: &es51986::BaseUnit) -> &'static str {
+  match base {
+    es51986::BaseUnit::Ampere => "A",
+    es51986::BaseUnit::Volt => "V",
+    es51986::BaseUnit::Ohm => "Ohm",
+    es51986::BaseUnit::Hearts => "Hz",
+    es51986::BaseUnit::Farad => "F",
+  }
+}
+
+fn opt<T: ToString>(v: Option<T>) -> String {
+  v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+impl OutputFormat {
+  /// The line to be written before the first record, if the format has one.
+  pub fn header(&self) -> Option<String> {
+    match self {
+      OutputFormat::Jsonl => None,
+      OutputFormat::Csv => Some("function,range,ac,dc,auto,overflow,battery_depleted,value,unit,calibrated,calibration_offset,calibration_gain,calibration_hash,rel_reference,rel_delta,rel_percent,alarms,captured,smoothed,channels,window_count,window_min,window_max,window_mean".to_owned()),
+    }
+  }
+
+  /// Format the data into a single line without the line terminator.
+  pub fn format(&self, reading: &Reading) -> String {
+    match self {
+      OutputFormat::Jsonl => serde_json::to_string(reading).unwrap(),
+      OutputFormat::Csv => {
+        let data = &reading.raw;
+        let (value, unit) = match &reading.value {
+          Some(v) => (
+            format!("{}{}", if data.status.sign.clone().is_minus() { "-" } else { "" }, v.digits),
+            format!("{}{}", prefix_symbol(&v.value_unit.prefix_unit), base_unit_symbol(&v.value_unit.base_unit)),
+          ),
+          None => ("".to_owned(), "".to_owned()),
+        };
+        format!(
+          "{:?},{:?},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+          data.function, data.range, data.option2.is_ac, data.option2.is_dc, data.option2.is_auto,
+          data.status.is_overflow, data.status.is_battery_depleted, value, unit,
+          opt(reading.calibration.as_ref().map(|c| c.value)), opt(reading.calibration.as_ref().map(|c| c.offset)),
+          opt(reading.calibration.as_ref().map(|c| c.gain)), opt(reading.calibration.as_ref().map(|c| &c.hash)),
+          opt(reading.rel.as_ref().map(|r| r.reference)), opt(reading.rel.as_ref().map(|r| r.delta)),
+          opt(reading.rel.as_ref().and_then(|r| r.percent)), reading.alarms.join(";"),
+          reading.captured, opt(reading.smoothed), scaling::format_channels(&reading.channels), opt(reading.window.as_ref().map(|w| w.count)), opt(reading.window.as_ref().and_then(|w| w.min)),
+          opt(reading.window.as_ref().and_then(|w| w.max)), opt(reading.window.as_ref().and_then(|w| w.mean)),
+        )
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{calibration::Calibration, reading::Reading, rel::Rel};
+  use super::OutputFormat;
+
+  #[test]
+  fn csv() {
+    let r = Reading::parse_frame(b"109853802");
+    assert_eq!(OutputFormat::Csv.format(&r), "Ohm,Range1,false,false,true,false,false,0.985,kOhm,,,,,,,,,false,,,,,,");
+
+    let r = Reading::parse_frame(b"00000;<0:");
+    assert_eq!(OutputFormat::Csv.format(&r), "Voltage,Range0,false,true,true,false,false,-0.000,V,,,,,,,,,false,,,,,,");
+
+    let mut r = Reading::parse_frame(b"00002;80:");
+    r.calibration = Some(Calibration { value: 0.003, offset: 0.001, gain: 1.0, hash: "9f86d081".to_owned() });
+    assert_eq!(OutputFormat::Csv.format(&r), "Voltage,Range0,false,true,true,false,false,0.002,V,0.003,0.001,1,9f86d081,,,,,false,,,,,,");
+  }
+
+  #[test]
+  fn rel() {
+    let mut r = Reading::parse_frame(b"110203802");
+    Rel::new(Some(1000.0)).apply(&mut r);
+    assert_eq!(OutputFormat::Csv.format(&r), "Ohm,Range1,false,false,true,false,false,1.020,kOhm,,,,,1000,20,2,,false,,,,,,");
+    assert!(OutputFormat::Jsonl.format(&r).ends_with(r#","rel":{"reference":1000.0,"delta":20.0,"percent":2.0}}"#));
+  }
+
+  #[test]
+  fn jsonl() {
+    let r = Reading::parse_frame(b"00002;80:");
+    assert_eq!(
+      OutputFormat::Jsonl.format(&r),
+      r#"{"raw":{"range":"Range0","digits":{"digits":[0,0,0,2]},"function":"Voltage","status":{"temperature_unit":"Celsius","sign":false,"is_battery_depleted":false,"is_overflow":false},"option2":{"is_dc":true,"is_ac":false,"is_auto":true}},"value":{"digits":"0.002","value_unit":{"prefix_unit":"None","base_unit":"Volt"}}}"#
+    );
+  }
+}
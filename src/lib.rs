@@ -0,0 +1,26 @@
+//! Library to read the measured data from the M-6000M digital multimeter.
+//!
+//! The `acquisition` module reads the serial port and provides the stream of `reading::Reading`.
+//! The readings can be delivered to the outputs implementing `data_subscriber::DataSubscriber` through `pipeline::Pipeline`.
+
+pub mod acquisition;
+pub mod alarm;
+pub mod announcement;
+pub mod audio;
+pub mod binning;
+pub mod calibration;
+pub mod data_subscriber;
+pub mod filter;
+pub mod format;
+pub mod pipeline;
+pub mod queue;
+pub mod recording;
+pub mod reading;
+pub mod rel;
+pub mod scaling;
+pub mod smoothing;
+pub mod speech;
+pub mod stable;
+pub mod stats;
+pub mod tone;
+pub mod tts;
@@ -1,27 +1,35 @@
-use std::{fmt, sync::mpsc::{self}, thread, time::{Duration, Instant}};
-use cpal::traits::HostTrait;
-use data_subscriber::{DataSubscriber, StdoutDataSubscriber, VoiceboxDataSubscriber};
+use std::{fmt, time::Duration};
+use m6000m_rs::{acquisition::{self, AcquisitionErr, ReadingStream}, announcement::Announcer, pipeline::Pipeline, data_subscriber::{DataSubscriber, FileDataSubscriber, StdoutDataSubscriber, TcpDataSubscriber, ToneDataSubscriber, UdpDataSubscriber, VoiceboxDataSubscriber}, filter::FilteredDataSubscriber, recording::SpeechRecorder, speech::{SpeechScheduler, SpeechTrigger}, tts::{self, CachedTts, TtsBackend, TtsConfig}};
+use output::{OutputConfig, OutputKind};
 
-use arg::{Args, ArgsErr};
+use arg::{Args, ArgsErr, SubCommand};
+use device::pick_audio_output_device;
+use session::Session;
 use log::{error, info};
-use rodio::{DeviceTrait, Source};
 use serial::Port;
-use serialport::SerialPort;
 use tui::Tui;
 use clap::Parser;
 
 mod arg;
 mod tui;
 mod serial;
-mod format;
-mod data_subscriber;
+mod config;
+mod output;
+mod control;
+mod notifier;
+mod session;
+mod voice;
+mod device;
 
 #[derive(Debug, PartialEq)]
 enum AppErr {
     Aborted,
     NoAvailablePorts,
     SerialPortError(String),
-    AudioDeviceError(String)
+    AudioDeviceError(String),
+    ConfigError(String),
+    OutputError(String),
+    VoiceError(String),
 }
 
 impl fmt::Display for AppErr {
@@ -31,35 +39,37 @@ impl fmt::Display for AppErr {
             AppErr::NoAvailablePorts => write!(f, "No serial ports found in this system. Please confirm the device is connected."),
             AppErr::SerialPortError(msg) => write!(f, "Cannot access serial port: {}", msg),
             AppErr::AudioDeviceError(msg) => write!(f, "Audio device error: {}", msg),
+            AppErr::ConfigError(msg) => write!(f, "Configuration error: {}", msg),
+            AppErr::OutputError(msg) => write!(f, "Output error: {}", msg),
+            AppErr::VoiceError(msg) => write!(f, "Voice error: {}", msg),
         }
     }
 }
 
 fn finalize_args<P: Port>(mut args: Args, tui: &mut Tui, p: &P) -> Result<Args, AppErr> {
-    loop {
-        match args.error() {
-            None => return Ok(args),
-            Some(e) => {
-                match e {
-                    ArgsErr::PortNotSpecified => {
-                        match p.available_ports() {
-                            Ok(ports) => {
-                                if ports.is_empty() {
-                                    return Err(AppErr::NoAvailablePorts);
-                                }
+    match args.error() {
+        None => Ok(args),
+        Some(e) => {
+            match e {
+                ArgsErr::PortNotSpecified => {
+                    match p.available_ports() {
+                        Ok(ports) => {
+                            if ports.is_empty() {
+                                return Err(AppErr::NoAvailablePorts);
+                            }
 
-                                let port = tui.ask_port(ports);
-                                if port.is_none() {
-                                    return Err(AppErr::Aborted);
-                                } else {
-                                    args.port = port.map(|p| p.port_name);
-                                }
-                                return Ok(args);
+                            let port = tui.ask_port(ports);
+                            if port.is_none() {
+                                return Err(AppErr::Aborted);
+                            } else {
+                                args.port = port.map(|p| p.port_name);
                             }
-                            Err(err) => return Err(AppErr::SerialPortError(err.to_string())),
+                            Ok(args)
                         }
+                        Err(err) => Err(AppErr::SerialPortError(err.to_string())),
                     }
                 }
+                e => Err(AppErr::ConfigError(e.to_string())),
             }
         }
     }
@@ -69,7 +79,10 @@ fn finalize_args<P: Port>(mut args: Args, tui: &mut Tui, p: &P) -> Result<Args,
 async fn get_args() -> Result<Args, AppErr> {
     use serial::SerialPort;
 
-    let args: Args = Args::parse();
+    let args: Args = config::resolve(Args::parse()).map_err(|e| AppErr::ConfigError(e.to_string()))?;
+    if args.command.is_some() {
+        return Ok(args);
+    }
     let mut tui = Tui {};
     let port = SerialPort {};
     
@@ -78,172 +91,162 @@ async fn get_args() -> Result<Args, AppErr> {
 
 #[cfg(test)]
 async fn get_args() -> Result<Args, AppErr> {
-    Ok(Args::parse())
+    config::resolve(Args::parse()).map_err(|e| AppErr::ConfigError(e.to_string()))
 }
 
-fn send_break(ser: &Box<dyn serialport::SerialPort>) {
-    match ser.set_break() {
-        Ok(_) => {
-            thread::sleep(Duration::from_millis(1000));
-        }
-        Err(err) => {
-            error!("Serial port access error. Cannot send break signal {:?}", err);
-            std::process::exit(1);
-        }
-    }
+/// How long to wait for the outputs to process the remaining readings at exit.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
-    match ser.clear_break() {
-        Ok(_) => {
-        }
-        Err(err) => {
-            error!("Serial port access error. Cannot send break signal {:?}", err);
-            thread::sleep(Duration::from_millis(1000));
-        }
-    }
+/// Text-to-speech backend with the cache.
+fn tts_backend(tts: &TtsConfig, args: &Args) -> Box<dyn TtsBackend> {
+    Box::new(CachedTts::new(tts.backend(), tts::DEFAULT_MEMORY_CACHE_CAPACITY, args.tts_cache.clone()))
 }
 
-fn send_break_if_needed(ser: &Box<dyn serialport::SerialPort>, last_received: Instant, timeout: Duration) -> bool{
-    let elapsed: Duration = last_received.elapsed();
-    
-    if timeout < elapsed {
-        info!("No data comes from the device. Sending break signal...");
-        send_break(ser);
-        info!("Sending break signal done.");
-        true
-    } else {
-        false
-    }
-}
-
-fn launch_serialport_worker(mut ser: Box<dyn serialport::SerialPort>, timeout: Duration) -> mpsc::Receiver<Vec<u8>> {
-    let (tx, rx): (mpsc::Sender<Vec<u8>>, mpsc::Receiver<Vec<u8>>) = mpsc::channel();
-    tokio::spawn(async move {
-        let mut last_received_time: Instant = Instant::now();
-        let mut buf: [u8; 64] = [0; 64];
-        loop {
-            match ser.read(&mut buf) {
-                Ok(read_size) => {
-                    if read_size != 0 {
-                        last_received_time = Instant::now();
-                        let vec: Vec<u8> = buf[0..read_size].to_vec();
-                        match tx.send(vec) {
-                            Ok(_) => {}
-                            Err(err) => {
-                                error!("Fatal error! Failed to communicate worker thread: {:?}", err);
-                                std::process::exit(1);
-                            }
-                        }
-                    } else {
-                        if send_break_if_needed(&ser, last_received_time, timeout) {
-                            last_received_time = Instant::now();
-                        }
-                    }
-                }
-                Err(err) => {
-                    if err.kind() == std::io::ErrorKind::TimedOut {
-                        if send_break_if_needed(&ser, last_received_time, timeout) {
-                            last_received_time = Instant::now();
-                        }
-                    } else {
-                        error!("Fatal error! Cannot receive from serial port: {:?}", err);
-                        std::process::exit(1);
-                    }
-                }
-            }
+fn build_subscriber(o: OutputConfig, args: &Args, speech_trigger: &SpeechTrigger) -> Result<Box<dyn DataSubscriber>, AppErr> {
+    let format = o.format.unwrap_or(args.output_format());
+    let subscriber: Box<dyn DataSubscriber> = match o.kind {
+        OutputKind::Stdout {} => Box::new(StdoutDataSubscriber::new(format)),
+        OutputKind::File { path, append } => Box::new(
+            FileDataSubscriber::new(format, path.clone(), append)
+            .map_err(|e| AppErr::OutputError(format!("Cannot open '{}': {}", path.display(), e)))?
+        ),
+        OutputKind::Tcp { address } => Box::new(TcpDataSubscriber::new(format, address)),
+        OutputKind::Udp { address } => Box::new(
+            UdpDataSubscriber::new(format, address.clone())
+            .map_err(|e| AppErr::OutputError(format!("Cannot send to '{}': {}", address, e)))?
+        ),
+        OutputKind::Voicebox { url, speaker, audio_output_device_name, locale, template } => Box::new(
+            VoiceboxDataSubscriber::new(
+                tts_backend(&TtsConfig::Voicevox { url, speaker: speaker.or(args.voicebox_speaker.clone()), tuning: args.tune(Default::default()) }, args),
+                Some(pick_audio_output_device(&audio_output_device_name.or(args.audio_output_device_name.clone()))?),
+                None,
+                Announcer::new(&locale.unwrap_or(args.voice_locale()), template.or(args.voice_template.clone())),
+                SpeechScheduler::new(args.speech(), speech_trigger.clone()),
+            )
+        ),
+        OutputKind::Speech { tts, audio_output_device_name, locale, template, recording } => {
+            let recording = recording.unwrap_or(args.recording());
+            let device = if recording.playback {
+                Some(pick_audio_output_device(&audio_output_device_name.or(args.audio_output_device_name.clone()))?)
+            } else {
+                None
+            };
+            Box::new(VoiceboxDataSubscriber::new(
+                tts_backend(&tts, args),
+                device,
+                recording.records().then(|| SpeechRecorder::new(&recording)),
+                Announcer::new(&locale.unwrap_or(args.voice_locale()), template.or(args.voice_template.clone())),
+                SpeechScheduler::new(args.speech(), speech_trigger.clone()),
+            ))
         }
-    });
-    rx
+        OutputKind::Tone { tone, audio_output_device_name } => Box::new(
+            ToneDataSubscriber::new(pick_audio_output_device(&audio_output_device_name.or(args.audio_output_device_name.clone()))?, tone)
+        ),
+    };
+    Ok(Box::new(FilteredDataSubscriber::new(o.filter.stages(), subscriber)))
 }
 
-fn open_serialport(args: &Args) -> Result<Box<dyn SerialPort>, AppErr> {
-    serialport::new(args.port.as_ref().unwrap(), 19200)
-    .data_bits(serialport::DataBits::Seven)
-    .parity(serialport::Parity::Odd)
-    .stop_bits(serialport::StopBits::One)
-    .timeout(Duration::from_millis(1000))
-    .open()
-    .map_err(|e| AppErr::SerialPortError(e.to_string()))
+/// Wait for the next tick. Never completes if there is no timer.
+async fn tick(timer: &mut Option<tokio::time::Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
 }
 
-fn pick_audio_output_device(args: &Args) -> Result<rodio::Device, AppErr> {
-    let host: cpal::Host = cpal::default_host();
-    match &args.audio_output_device_name {
-        None => return Ok(host.default_output_device().unwrap().into()),
-        Some(output_device_name) => {
-            let mut devices: Vec<cpal::Device> = host.output_devices().map_err(|e| AppErr::AudioDeviceError(e.to_string()))?.collect();
-            if let Some(dev) = devices.iter().find(|d| &d.name().unwrap_or("".to_owned()) == output_device_name) {
-                return Ok(dev.clone().into());
-            }
-            eprintln!("Unknown audio output device name: {}", output_device_name);
-            eprintln!("{} audio output device detected:", devices.len());
-            for dev in devices {
-                eprintln!("  Device '{}'", dev.name().unwrap_or("Unknown".to_owned()));
-                match dev.supported_output_configs() {
-                    Ok(configs) => {
-                        for config in configs {
-                            println!("    Configuration:");
-                            println!("      Channels: {:?}", config.channels());
-                            println!("      Sample rate: {:?}", config.min_sample_rate().0);
-                            println!("      Sample format: {:?}", config.sample_format());
-                        }
-                    },
-                    Err(e) => println!("    Cannot retrieve configuration: {}", e),
-                }
-                eprintln!();
-            }
-            return Err(AppErr::AudioDeviceError(format!("Device name '{}' is not found.", output_device_name)));
+/// Run the subcommand instead of measuring.
+async fn run_command(command: SubCommand, args: Args) -> Result<(), AppErr> {
+    match command {
+        SubCommand::ListSpeakers => {
+            let url = args.voicevox_url().ok_or(AppErr::VoiceError("--voicebox-url is not specified.".to_owned()))?;
+            tokio::task::spawn_blocking(move || voice::list_speakers(&url)).await.map_err(|e| AppErr::VoiceError(e.to_string()))?
         }
+        SubCommand::ListAudioDevices => device::list_audio_devices(),
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), AppErr> {
-    use std::sync::mpsc;
-    use serialport::SerialPort;
-
     env_logger::init();
     let args = get_args().await?;
-    let timeout: Duration = Duration::from_secs(3);
-    let ser: Box<dyn SerialPort> = open_serialport(&args)?;
-    let rx: mpsc::Receiver<Vec<u8>> = launch_serialport_worker(ser, timeout);
-    let mut parser = es51986::parser::Parser::new();
-    let mut subscribers: Vec<Box<dyn DataSubscriber>> = vec![Box::new(StdoutDataSubscriber::new(args.output_format.clone()))];
-    if let Some(voicebox_url) = &args.voicebox_url {
-        let audio_output_device = pick_audio_output_device(&args)?;
-        subscribers.push(
-            Box::new(VoiceboxDataSubscriber::new(
-                voicebox_url.clone(),
-                args.voicebox_speaker,
-                audio_output_device,
-            ))
-        )
+    if let Some(command) = args.command.clone() {
+        return run_command(command, args).await;
+    }
+    let mut outputs = output::effective_outputs(&args).map_err(|e| AppErr::OutputError(e.to_string()))?;
+    let mut voice = args.tts();
+    let (outputs, voice) = tokio::task::spawn_blocking(move || {
+        voice::resolve_speakers(&mut outputs, &mut voice)?;
+        voice::check_engines(&outputs, &voice).map(|_| (outputs, voice))
+    }).await.map_err(|e| AppErr::VoiceError(e.to_string()))??;
+
+    let ser = acquisition::open_serialport(args.port.as_ref().unwrap()).map_err(|e| AppErr::SerialPortError(e.to_string()))?;
+    let mut readings = ReadingStream::new(acquisition::launch_serialport_worker(ser, acquisition::DEFAULT_TIMEOUT));
+    let mut pipeline = Pipeline::new();
+    let speech_trigger = SpeechTrigger::default();
+    for o in outputs {
+        let (name, queue) = (o.name.clone(), o.queue.clone());
+        pipeline.add_subscriber(name, queue, build_subscriber(o, &args, &speech_trigger)?);
     }
 
-    loop {
-        let received: Vec<u8> = rx.recv().unwrap();
-        for r in parser.parse(&received) {
-            match r {
-                Ok(out) => {
-                    for s in subscribers.iter_mut() {
-                        s.on_data(&out);
+    let mut commands = control::launch_stdin_reader();
+    let mut session = Session::new(&args, voice, speech_trigger)?;
+    let mut stats_timer = args.stats_interval.map(|secs| {
+        let period = Duration::from_secs(secs.max(1));
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let result = loop {
+        tokio::select! {
+            r = readings.next() => match r {
+                Some(Ok(mut reading)) => {
+                    session.on_reading(&mut reading);
+                    // An output with the block policy may stall the publishing. Ctrl-C still stops it.
+                    tokio::select! {
+                        _ = pipeline.publish(reading) => {}
+                        _ = &mut ctrl_c => {
+                            info!("Interrupted.");
+                            break Ok(());
+                        }
                     }
                 }
-                Err(err) => error!("Error: {:?}", err),
+                Some(Err(AcquisitionErr::SerialPort(msg))) => break Err(AppErr::SerialPortError(msg)),
+                Some(Err(err)) => error!("Error: {}", err),
+                None => break Ok(()),
+            },
+            Some(cmd) = commands.recv() => session.on_command(cmd),
+            _ = tick(&mut stats_timer) => session.on_tick(),
+            _ = &mut ctrl_c => {
+                info!("Interrupted.");
+                break Ok(());
             }
         }
+    };
+    drop(readings);
+    let closed = tokio::time::timeout(CLOSE_TIMEOUT, pipeline.close()).await.is_ok();
+    session.finish();
+    if !closed {
+        // The runtime would wait for the stalled outputs forever.
+        error!("Outputs did not finish in {} seconds. Exiting without waiting for them.", CLOSE_TIMEOUT.as_secs());
+        if let Err(err) = &result {
+            eprintln!("Error: {:?}", err);
+        }
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
     }
-
-    Ok(())
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use serialport::{SerialPortInfo, SerialPortType};
-    use crate::{arg::{Args, OutputFormat}, finalize_args, serial::SerialPort, tui::Tui, AppErr};
+    use m6000m_rs::tts::TtsConfig;
+    use crate::{arg::{Args, TtsEngine}, finalize_args, serial::SerialPort, tui::Tui, AppErr};
 
     #[test]
     fn not_specify_port_no_available_ports() {
-        let args: Args = Args { port: None, output_format: OutputFormat::Jsonl, voicebox_url: None, voicebox_speaker: 1, audio_output_device_name: None };
+        let args: Args = Args { port: None, ..Default::default() };
         let mut tui = Tui {
             available_ports: None,
             port_to_return: None,
@@ -258,7 +261,7 @@ mod tests {
 
     #[test]
     fn port_is_specified_by_args() -> Result<(), AppErr> {
-        let args: Args = Args { port: Some("Port0".to_owned()), output_format: OutputFormat::Jsonl, voicebox_url: None, voicebox_speaker: 1, audio_output_device_name: None };
+        let args: Args = Args { port: Some("Port0".to_owned()), ..Default::default() };
         let mut tui = Tui {
             available_ports: None,
             port_to_return: None,
@@ -274,7 +277,7 @@ mod tests {
 
     #[test]
     fn port_is_not_specified_but_quit() {
-        let args: Args = Args { port: None, output_format: OutputFormat::Jsonl, voicebox_url: None, voicebox_speaker: 1, audio_output_device_name: None };
+        let args: Args = Args { port: None, ..Default::default() };
         let mut tui = Tui {
             available_ports: None,
             port_to_return: None,
@@ -294,7 +297,7 @@ mod tests {
 
     #[test]
     fn select_port() -> Result<(), AppErr> {
-        let args: Args = Args { port: None, output_format: OutputFormat::Jsonl, voicebox_url: None, voicebox_speaker: 1, audio_output_device_name: None };
+        let args: Args = Args { port: None, ..Default::default() };
         let available_ports = vec![
         SerialPortInfo {
             port_name: "port0".to_owned(),
@@ -322,4 +325,45 @@ mod tests {
         assert_eq!(args.port, Some("port1".to_owned()));
         Ok(())
     }
+
+    #[test]
+    fn incomplete_tts() {
+        let mut tui = Tui {
+            available_ports: None,
+            port_to_return: None,
+        };
+        let port = SerialPort {
+            available_ports: Ok(vec![])
+        };
+        for args in [
+            Args { port: Some("Port0".to_owned()), tts: Some(TtsEngine::Voicevox), ..Default::default() },
+            Args { port: Some("Port0".to_owned()), tts: Some(TtsEngine::Command), ..Default::default() },
+            Args { port: None, tts: Some(TtsEngine::Command), tts_command: Some("say 'unbalanced".to_owned()), ..Default::default() },
+        ] {
+            assert!(matches!(finalize_args(args, &mut tui, &port), Err(AppErr::ConfigError(_))));
+        }
+    }
+
+    #[test]
+    fn tts_command() -> Result<(), AppErr> {
+        let args: Args = Args {
+            port: Some("Port0".to_owned()),
+            tts: Some(TtsEngine::Command),
+            tts_command: Some("piper --model '/opt/voices/ja JP.onnx' -f {output}".to_owned()),
+            ..Default::default()
+        };
+        let mut tui = Tui {
+            available_ports: None,
+            port_to_return: None,
+        };
+        let port = SerialPort {
+            available_ports: Ok(vec![])
+        };
+
+        let args = finalize_args(args, &mut tui, &port)?;
+        assert_eq!(args.tts(), Some(TtsConfig::Command {
+            command: ["piper", "--model", "/opt/voices/ja JP.onnx", "-f", "{output}"].iter().map(|s| s.to_string()).collect(),
+        }));
+        Ok(())
+    }
 }
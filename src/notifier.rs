@@ -0,0 +1,59 @@
+use std::{sync::mpsc, thread, time::Duration};
+use log::{error, warn};
+use m6000m_rs::{audio::{self, Sound}, data_subscriber::{VoiceStatus, VoiceboxDataSubscriber}, tts::TtsBackend};
+
+/// Beep pattern used when the announcement is not spoken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beep {
+    pub frequency: f32,
+    pub duration: Duration,
+    pub count: usize,
+}
+
+/// Plays the announcements (alarms, binning results) in its own thread so that the measurement is not delayed.
+pub struct Notifier {
+    tx: mpsc::Sender<(Sound, String, Beep)>,
+}
+
+impl Notifier {
+    /// The announcements are spoken with the voice. They are beeped if there is no voice. Without the device, the
+    /// announcements are only synthesized (to report the voice status) and the beeps are skipped.
+    pub fn new(device: Option<rodio::Device>, mut voice: Option<Box<dyn TtsBackend>>) -> Self {
+        let (tx, rx) = mpsc::channel::<(Sound, String, Beep)>();
+        thread::spawn(move || {
+            let mut status = VoiceStatus::new();
+            for (sound, text, beep) in rx {
+                match (sound, &mut voice) {
+                    (Sound::None, _) => {}
+                    (Sound::Speak, Some(voice)) => {
+                        status.report(&VoiceboxDataSubscriber::speak(voice.as_mut(), text, device.as_ref(), None));
+                    }
+                    (sound, voice) => {
+                        if sound == Sound::Speak && voice.is_none() {
+                            warn!("No text-to-speech engine is specified. Beep instead of speaking '{}'.", text);
+                        }
+                        if let Some(device) = &device {
+                            Self::beep(device, &beep);
+                        }
+                    }
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    fn beep(device: &rodio::Device, beep: &Beep) {
+        for _ in 0..beep.count {
+            if let Err(err) = audio::beep(device, beep.frequency, beep.duration) {
+                error!("{}", err);
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Speak the text or play the beep according to the sound.
+    pub fn announce(&self, sound: Sound, text: String, beep: Beep) {
+        let _ = self.tx.send((sound, text, beep));
+    }
+}
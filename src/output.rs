@@ -0,0 +1,532 @@
+use std::{collections::HashSet, fmt, path::PathBuf, time::Instant};
+use serde::{de::{DeserializeOwned, MapAccess, Visitor}, Deserialize, Deserializer};
+use m6000m_rs::{announcement::Locale, filter::{AggregateConfig, Aggregation, Deadband, DeadbandConfig, Filter}, queue::QueueConfig, reading::Reading, recording::RecordingConfig, tone::ToneConfig, tts::{Speaker, TtsConfig}};
+use crate::arg::{Args, OutputFormat};
+
+/// An output declared in the configuration file.
+///
+/// ```toml
+/// [[outputs]]
+/// name = "log"
+/// type = "file"
+/// path = "measure.csv"
+/// format = "csv"
+/// filter = { functions = ["Voltage"], skip-overflow = true, deadband = { absolute = "1m", heartbeat = 10 } }
+/// queue = { capacity = 1024, overflow = "block" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OutputConfig {
+  pub name: String,
+  #[serde(default = "enabled_default")]
+  pub enabled: bool,
+  /// Format of the records. The --output-format is used if omitted. Not used by the voice output.
+  #[serde(default)]
+  pub format: Option<OutputFormat>,
+  #[serde(default)]
+  pub filter: FilterConfig,
+  /// Queue of the readings waiting for the output.
+  #[serde(default)]
+  pub queue: QueueConfig,
+  #[serde(flatten)]
+  pub kind: OutputKind,
+}
+
+fn enabled_default() -> bool {
+  true
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", rename_all_fields = "kebab-case", deny_unknown_fields)]
+pub enum OutputKind {
+  /// A struct variant so that the unknown keys are rejected.
+  Stdout {},
+  File {
+    path: PathBuf,
+    /// Append to the file instead of truncating it.
+    #[serde(default)]
+    append: bool,
+  },
+  /// Connect to the address and send records. Reconnects when the connection is lost.
+  Tcp { address: String },
+  /// Send each record as a datagram.
+  Udp { address: String },
+  /// Speak with the VOICEVOX compatible engine. Same as the speech output with engine = "voicevox".
+  Voicebox {
+    url: String,
+    #[serde(default)]
+    speaker: Option<Speaker>,
+    #[serde(default)]
+    audio_output_device_name: Option<String>,
+    /// Language of the announcements. The --voice-locale is used if omitted.
+    #[serde(default)]
+    locale: Option<Locale>,
+    /// Template of the announcements. The --voice-template is used if omitted.
+    #[serde(default)]
+    template: Option<String>,
+  },
+  /// Speak with a text-to-speech engine.
+  Speech {
+    #[serde(flatten, deserialize_with = "deserialize_tts")]
+    tts: TtsConfig,
+    #[serde(default)]
+    audio_output_device_name: Option<String>,
+    #[serde(default)]
+    locale: Option<Locale>,
+    #[serde(default)]
+    template: Option<String>,
+    /// Where the speech is played and written. The --speech-wav options are used if omitted.
+    #[serde(default)]
+    recording: Option<RecordingConfig>,
+  },
+  /// Sound a tone following the readings.
+  Tone {
+    #[serde(flatten, deserialize_with = "deserialize_tone")]
+    tone: ToneConfig,
+    #[serde(default)]
+    audio_output_device_name: Option<String>,
+  },
+}
+
+/// Deserialize a flattened enum from the given keys of the enclosing table. The keys are taken so that serde can reject
+/// the keys left unknown, which it cannot do when the enum looks at the whole table.
+fn deserialize_flattened<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D, keys: &'static [&'static str]) -> Result<T, D::Error> {
+  struct TableVisitor;
+
+  impl<'de> Visitor<'de> for TableVisitor {
+    type Value = toml::Table;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "a table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<toml::Table, A::Error> {
+      let mut table = toml::Table::new();
+      while let Some((key, value)) = map.next_entry::<String, toml::Value>()? {
+        table.insert(key, value);
+      }
+      Ok(table)
+    }
+  }
+
+  let table = d.deserialize_struct("flattened", keys, TableVisitor)?;
+  T::deserialize(toml::Value::Table(table)).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_tts<'de, D: Deserializer<'de>>(d: D) -> Result<TtsConfig, D::Error> {
+  deserialize_flattened(d, TtsConfig::KEYS)
+}
+
+fn deserialize_tone<'de, D: Deserializer<'de>>(d: D) -> Result<ToneConfig, D::Error> {
+  deserialize_flattened(d, ToneConfig::KEYS)
+}
+
+/// Conditions for the records to be passed to an output.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FilterConfig {
+  /// Functions to pass. All functions pass if empty.
+  pub functions: Vec<es51986::Function>,
+  /// Drop the records in overflow state.
+  pub skip_overflow: bool,
+  /// Drop the records that have no value.
+  pub skip_no_value: bool,
+  /// Pass only the captured readings. Defaults to true in capture mode.
+  pub captured_only: Option<bool>,
+  /// Pass a single reading with min, max and mean per time window. The --aggregate is used if omitted.
+  pub aggregate: Option<AggregateConfig>,
+  /// Pass only the readings that changed beyond the deadband. The --deadband options are used if omitted.
+  pub deadband: Option<DeadbandConfig>,
+}
+
+impl FilterConfig {
+  pub fn accepts(&self, reading: &Reading) -> bool {
+    let data = &reading.raw;
+    if !self.functions.is_empty() && !self.functions.contains(&data.function) {
+      return false;
+    }
+    if self.skip_overflow && data.status.is_overflow {
+      return false;
+    }
+    if self.skip_no_value && reading.value.is_none() {
+      return false;
+    }
+    if self.captured_only == Some(true) && !reading.captured {
+      return false;
+    }
+    true
+  }
+
+  /// Filter stages of an output in the order to be applied.
+  pub fn stages(&self) -> Vec<Box<dyn Filter>> {
+    let mut stages: Vec<Box<dyn Filter>> = vec![Box::new(self.clone())];
+    if let Some(aggregate) = &self.aggregate {
+      stages.push(Box::new(Aggregation::new(aggregate.clone())));
+    }
+    if let Some(deadband) = &self.deadband {
+      stages.push(Box::new(Deadband::new(deadband.clone())));
+    }
+    stages
+  }
+}
+
+impl Filter for FilterConfig {
+  fn apply(&mut self, reading: Reading, _now: Instant) -> Vec<Reading> {
+    if self.accepts(&reading) { vec![reading] } else { vec![] }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputErr {
+  DuplicatedName(String),
+  UnknownName(String),
+  /// Several speech outputs write the same track file.
+  SharedTrack(PathBuf),
+}
+
+impl fmt::Display for OutputErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      OutputErr::DuplicatedName(name) => write!(f, "Output name '{}' is declared more than once.", name),
+      OutputErr::UnknownName(name) => write!(f, "Unknown output name '{}'.", name),
+      OutputErr::SharedTrack(path) => write!(f, "Speech track '{}' is written by more than one output. Give each speech output its own recording track.", path.display()),
+    }
+  }
+}
+
+pub const STDOUT_OUTPUT_NAME: &str = "stdout";
+pub const VOICEBOX_OUTPUT_NAME: &str = "voicebox";
+pub const TONE_OUTPUT_NAME: &str = "tone";
+
+/// Outputs to be used. If the configuration file declares no outputs, the standard output is used. The --voicebox-url or
+/// the --tts adds a voice output and the --tone adds a tone output. The tone outputs receive every reading unless their
+/// filter says otherwise, since the tone stops when the readings stop. The voicebox outputs are turned into the speech outputs. The --enable-output and --disable-output are applied at last and disabled outputs are removed.
+pub fn effective_outputs(args: &Args) -> Result<Vec<OutputConfig>, OutputErr> {
+  let mut outputs: Vec<OutputConfig> = args.outputs.clone().unwrap_or_else(|| vec![
+    OutputConfig {
+      name: STDOUT_OUTPUT_NAME.to_owned(),
+      enabled: true,
+      format: None,
+      filter: FilterConfig::default(),
+      queue: QueueConfig::default(),
+      kind: OutputKind::Stdout {},
+    }
+  ]);
+
+  if let Some(tts) = args.tts() {
+    if !outputs.iter().any(|o| o.name == VOICEBOX_OUTPUT_NAME) {
+      outputs.push(OutputConfig {
+        name: VOICEBOX_OUTPUT_NAME.to_owned(),
+        enabled: true,
+        format: None,
+        filter: FilterConfig::default(),
+        queue: QueueConfig::default(),
+        kind: OutputKind::Speech {
+          tts,
+          audio_output_device_name: args.audio_output_device_name.clone(),
+          locale: None,
+          template: None,
+          recording: None,
+        },
+      });
+    }
+  }
+
+  if let Some(tone) = args.tone() {
+    if !outputs.iter().any(|o| o.name == TONE_OUTPUT_NAME) {
+      outputs.push(OutputConfig {
+        name: TONE_OUTPUT_NAME.to_owned(),
+        enabled: true,
+        format: None,
+        filter: FilterConfig::default(),
+        queue: QueueConfig::default(),
+        kind: OutputKind::Tone { tone, audio_output_device_name: args.audio_output_device_name.clone() },
+      });
+    }
+  }
+
+  let mut names: HashSet<&str> = HashSet::new();
+  for o in outputs.iter() {
+    if !names.insert(&o.name) {
+      return Err(OutputErr::DuplicatedName(o.name.clone()));
+    }
+  }
+
+  for (names, enabled) in [(&args.enable_output, true), (&args.disable_output, false)] {
+    for name in names {
+      match outputs.iter_mut().find(|o| &o.name == name) {
+        Some(o) => o.enabled = enabled,
+        None => return Err(OutputErr::UnknownName(name.clone())),
+      }
+    }
+  }
+
+  outputs.retain(|o| o.enabled);
+  let capture = args.capture().is_some();
+  let deadband = args.deadband();
+  for o in outputs.iter_mut() {
+    if o.format.is_none() {
+      o.format = Some(args.output_format());
+    }
+    let tone = matches!(o.kind, OutputKind::Tone { .. });
+    o.filter.captured_only = Some(o.filter.captured_only.unwrap_or(capture && !tone));
+    if let OutputKind::Voicebox { url, speaker, audio_output_device_name, locale, template } = &o.kind {
+      o.kind = OutputKind::Speech {
+        tts: TtsConfig::Voicevox { url: url.clone(), speaker: speaker.clone(), tuning: Default::default() },
+        audio_output_device_name: audio_output_device_name.clone(),
+        locale: locale.clone(),
+        template: template.clone(),
+        recording: None,
+      };
+    }
+    if let OutputKind::Speech { tts, recording, .. } = &mut o.kind {
+      if let TtsConfig::Voicevox { speaker, tuning, .. } = tts {
+        *speaker = Some(speaker.clone().unwrap_or(args.voicebox_speaker()));
+        *tuning = args.tune(tuning.clone());
+      }
+      *recording = Some(recording.clone().unwrap_or(args.recording()));
+    }
+    if tone {
+      continue;
+    }
+    if o.filter.aggregate.is_none() {
+      o.filter.aggregate = args.aggregate.map(|window| AggregateConfig { window });
+    }
+    if o.filter.deadband.is_none() {
+      o.filter.deadband = deadband.clone();
+    }
+  }
+
+  let mut tracks: HashSet<&PathBuf> = HashSet::new();
+  for o in outputs.iter() {
+    if let OutputKind::Speech { recording: Some(RecordingConfig { track: Some(track), .. }), .. } = &o.kind {
+      if !tracks.insert(track) {
+        return Err(OutputErr::SharedTrack(track.clone()));
+      }
+    }
+  }
+  Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+  use m6000m_rs::{announcement::Locale, filter::{AggregateConfig, DeadbandConfig}, reading::Reading, recording::RecordingConfig, tone::ToneConfig, tts::{Speaker, TtsConfig}};
+  use crate::{arg::{Args, OutputFormat, ToneMode, TtsEngine}, config::Config};
+  use super::{effective_outputs, OutputErr, OutputKind};
+
+  #[test]
+  fn stdout_by_default() {
+    let outputs = effective_outputs(&Args::default()).unwrap();
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(outputs[0].kind, OutputKind::Stdout {});
+    assert_eq!(outputs[0].format, Some(OutputFormat::Jsonl));
+
+    let args = Args { disable_output: vec!["stdout".to_owned()], ..Default::default() };
+    assert!(effective_outputs(&args).unwrap().is_empty());
+  }
+
+  #[test]
+  fn declared_outputs() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "stdout"
+type = "stdout"
+format = "csv"
+
+[[outputs]]
+name = "log"
+type = "file"
+path = "log.jsonl"
+enabled = false
+filter = { functions = ["Voltage"] }
+"#, path).unwrap();
+    let args = Args {
+      voicebox_url: Some("http://localhost:50021".to_owned()),
+      enable_output: vec!["log".to_owned()],
+      ..Default::default()
+    }.apply(config.settings(None, path).unwrap());
+
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(), vec!["stdout", "log", "voicebox"]);
+    assert_eq!(outputs[0].format, Some(OutputFormat::Csv));
+    assert_eq!(outputs[1].format, Some(OutputFormat::Jsonl));
+    assert_eq!(outputs[1].filter.functions, vec![es51986::Function::Voltage]);
+    assert_eq!(outputs[1].kind, OutputKind::File { path: "log.jsonl".into(), append: false });
+  }
+
+  #[test]
+  fn capture_mode() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "stdout"
+type = "stdout"
+
+[[outputs]]
+name = "raw"
+type = "file"
+path = "raw.jsonl"
+filter = { captured-only = false }
+"#, path).unwrap();
+    let args = Args { capture: true, ..Default::default() }.apply(config.settings(None, path).unwrap());
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs[0].filter.captured_only, Some(true));
+    assert_eq!(outputs[1].filter.captured_only, Some(false));
+
+    let mut r = Reading::new(es51986::Output::parse(b"109853802").unwrap());
+    assert!(!outputs[0].filter.accepts(&r));
+    assert!(outputs[1].filter.accepts(&r));
+    r.captured = true;
+    assert!(outputs[0].filter.accepts(&r));
+  }
+
+  #[test]
+  fn voicebox_locale() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "voice"
+type = "voicebox"
+url = "http://localhost:50021"
+locale = "en"
+template = "{value} {prefix}{unit}"
+"#, path).unwrap();
+    let outputs = effective_outputs(&Args::default().apply(config.settings(None, path).unwrap())).unwrap();
+    match &outputs[0].kind {
+      OutputKind::Speech { tts, locale, template, .. } => {
+        assert_eq!(tts, &TtsConfig::Voicevox { url: "http://localhost:50021".to_owned(), speaker: Some(Speaker::Id(1)), tuning: Default::default() });
+        assert_eq!(locale, &Some(Locale::En));
+        assert_eq!(template.as_deref(), Some("{value} {prefix}{unit}"));
+      }
+      kind => panic!("Unexpected output {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn speech() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "voice"
+type = "speech"
+engine = "espeak-ng"
+voice = "en-us"
+"#, path).unwrap();
+    let outputs = effective_outputs(&Args::default().apply(config.settings(None, path).unwrap())).unwrap();
+    assert!(matches!(&outputs[0].kind, OutputKind::Speech { tts: TtsConfig::EspeakNg { voice: Some(v) }, .. } if v == "en-us"));
+
+    assert!(matches!(&outputs[0].kind, OutputKind::Speech { recording: Some(r), .. } if r == &RecordingConfig::default()));
+
+    let args = Args { tts: Some(TtsEngine::OpenJtalk), speech_wav_dir: Some("speech".into()), no_speech_playback: true, ..Default::default() };
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs[1].name, "voicebox");
+    assert!(matches!(&outputs[1].kind, OutputKind::Speech { tts: TtsConfig::OpenJtalk { .. }, .. }));
+    let expected = RecordingConfig { directory: Some("speech".into()), track: None, playback: false };
+    assert!(matches!(&outputs[1].kind, OutputKind::Speech { recording: Some(r), .. } if r == &expected));
+  }
+
+  #[test]
+  fn tone() {
+    let args = Args { tone: Some(ToneMode::Continuity), tone_threshold: Some(10.0), capture: true, deadband: Some(0.001), ..Default::default() };
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs[1].name, "tone");
+    assert_eq!(outputs[1].kind, OutputKind::Tone { tone: ToneConfig::Continuity { threshold: 10.0, frequency: 2000.0 }, audio_output_device_name: None });
+    assert_eq!(outputs[1].filter.captured_only, Some(false));
+    assert_eq!(outputs[1].filter.deadband, None);
+
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "tuning"
+type = "tone"
+mode = "pitch"
+low = "4.5"
+high = "5.5"
+"#, path).unwrap();
+    let outputs = effective_outputs(&Args::default().apply(config.settings(None, path).unwrap())).unwrap();
+    assert_eq!(outputs[0].kind, OutputKind::Tone { tone: ToneConfig::Pitch { low: 4.5, high: Some(5.5), min_frequency: 220.0, max_frequency: 1760.0 }, audio_output_device_name: None });
+  }
+
+  #[test]
+  fn shared_track() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "voice"
+type = "speech"
+engine = "espeak-ng"
+"#, path).unwrap();
+    let args = Args { tts: Some(TtsEngine::OpenJtalk), speech_wav_track: Some("session.wav".into()), ..Default::default() };
+    let args = args.apply(config.settings(None, path).unwrap());
+    assert_eq!(effective_outputs(&args).err().unwrap(), OutputErr::SharedTrack("session.wav".into()));
+    let args = Args { speech_wav_track: None, ..args };
+    assert!(effective_outputs(&args).is_ok());
+  }
+
+  #[test]
+  fn unknown_output_name() {
+    let args = Args { disable_output: vec!["foo".to_owned()], ..Default::default() };
+    assert_eq!(effective_outputs(&args).err().unwrap(), OutputErr::UnknownName("foo".to_owned()));
+  }
+
+  #[test]
+  fn deadband() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "stdout"
+type = "stdout"
+
+[[outputs]]
+name = "log"
+type = "file"
+path = "log.csv"
+filter = { deadband = { absolute = "10m", heartbeat = 60 } }
+"#, path).unwrap();
+    let args = Args { deadband: Some(0.001), heartbeat: Some(5.0), ..Default::default() }.apply(config.settings(None, path).unwrap());
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs[0].filter.deadband, Some(DeadbandConfig { absolute: 0.001, relative: 0.0, heartbeat: Some(5.0) }));
+    assert_eq!(outputs[1].filter.deadband, Some(DeadbandConfig { absolute: 0.01, relative: 0.0, heartbeat: Some(60.0) }));
+    assert_eq!(outputs[1].filter.stages().len(), 2);
+
+    assert_eq!(effective_outputs(&Args::default()).unwrap()[0].filter.stages().len(), 1);
+  }
+
+  #[test]
+  fn aggregate() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "stdout"
+type = "stdout"
+
+[[outputs]]
+name = "soak"
+type = "file"
+path = "soak.csv"
+filter = { aggregate = { window = 60 } }
+"#, path).unwrap();
+    let args = Args { aggregate: Some(1.0), ..Default::default() }.apply(config.settings(None, path).unwrap());
+    let outputs = effective_outputs(&args).unwrap();
+    assert_eq!(outputs[0].filter.aggregate, Some(AggregateConfig { window: 1.0 }));
+    assert_eq!(outputs[1].filter.aggregate, Some(AggregateConfig { window: 60.0 }));
+    assert_eq!(outputs[1].filter.stages().len(), 2);
+  }
+
+  #[test]
+  fn filter() {
+    let path = Path::new("config.toml");
+    let config = Config::parse(r#"
+[[outputs]]
+name = "stdout"
+type = "stdout"
+filter = { functions = ["Ohm"], skip-overflow = true }
+"#, path).unwrap();
+    let filter = config.settings.outputs.unwrap()[0].filter.clone();
+    assert!(filter.accepts(&Reading::new(es51986::Output::parse(b"109853802").unwrap())));
+    assert!(!filter.accepts(&Reading::new(es51986::Output::parse(b"560003902").unwrap())));
+    assert!(!filter.accepts(&Reading::new(es51986::Output::parse(b"00002;80:").unwrap())));
+  }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use log::{info, warn};
+use tokio::task::JoinHandle;
+use crate::{data_subscriber::DataSubscriber, queue::{Queue, QueueConfig}, reading::Reading};
+
+/// Delivers the readings to the subscribers. Each subscriber has its own queue and each DataSubscriber runs in its own
+/// blocking task so that a slow subscriber does not stall the others or the acquisition unless its queue blocks.
+/// Async consumers can receive the readings with subscribe().
+pub struct Pipeline {
+  queues: Vec<(String, Arc<Queue>)>,
+  tasks: Vec<JoinHandle<()>>,
+}
+
+impl Default for Pipeline {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Pipeline {
+  pub fn new() -> Self {
+    Self { queues: vec![], tasks: vec![] }
+  }
+
+  pub fn subscribe(&mut self, name: String, config: QueueConfig) -> Arc<Queue> {
+    let queue = Arc::new(Queue::new(config));
+    self.queues.push((name, queue.clone()));
+    queue
+  }
+
+  /// Run the subscriber until the pipeline is closed.
+  pub fn add_subscriber(&mut self, name: String, config: QueueConfig, mut subscriber: Box<dyn DataSubscriber>) {
+    let queue = self.subscribe(name.clone(), config);
+    let handle = tokio::runtime::Handle::current();
+    self.tasks.push(tokio::task::spawn_blocking(move || {
+      let mut reported: u64 = 0;
+      while let Some(reading) = handle.block_on(queue.recv()) {
+        let dropped = queue.dropped();
+        if reported < dropped {
+          warn!("Output '{}' is too slow. {} readings are dropped ({} in total).", name, dropped - reported, dropped);
+          reported = dropped;
+        }
+        subscriber.on_data(&reading);
+      }
+      subscriber.on_close();
+      info!("Output '{}' is closed.", name);
+    }));
+  }
+
+  pub async fn publish(&self, reading: Reading) {
+    for (_, q) in self.queues.iter() {
+      q.push(reading.clone()).await;
+    }
+  }
+
+  /// Number of the readings dropped for each subscriber.
+  pub fn dropped_counts(&self) -> Vec<(String, u64)> {
+    self.queues.iter().map(|(name, q)| (name.clone(), q.dropped())).collect()
+  }
+
+  /// Stop delivering and wait for the subscribers to process the readings already published.
+  pub async fn close(mut self) {
+    for (_, q) in self.queues.iter() {
+      q.close();
+    }
+    for t in self.tasks.drain(..) {
+      let _ = t.await;
+    }
+    for (name, dropped) in self.dropped_counts() {
+      if 0 < dropped {
+        warn!("{} readings are dropped for output '{}'.", dropped, name);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+  use crate::{data_subscriber::DataSubscriber, queue::QueueConfig, reading::Reading};
+  use super::Pipeline;
+
+  struct Recorder(Arc<Mutex<Vec<Reading>>>);
+
+  impl DataSubscriber for Recorder {
+    fn on_data(&mut self, data: &Reading) {
+      self.0.lock().unwrap().push(data.clone());
+    }
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn deliver_to_all_subscribers() {
+    let mut pipeline = Pipeline::new();
+    let received0 = Arc::new(Mutex::new(vec![]));
+    let received1 = Arc::new(Mutex::new(vec![]));
+    pipeline.add_subscriber("0".to_owned(), QueueConfig::default(), Box::new(Recorder(received0.clone())));
+    pipeline.add_subscriber("1".to_owned(), QueueConfig::default(), Box::new(Recorder(received1.clone())));
+    let queue = pipeline.subscribe("2".to_owned(), QueueConfig::default());
+
+    let r0 = Reading::parse_frame(b"109853802");
+    let r1 = Reading::parse_frame(b"00002;80:");
+    pipeline.publish(r0.clone()).await;
+    pipeline.publish(r1.clone()).await;
+    assert_eq!(queue.recv().await.unwrap(), r0);
+    pipeline.close().await;
+
+    assert_eq!(*received0.lock().unwrap(), vec![r0.clone(), r1.clone()]);
+    assert_eq!(*received1.lock().unwrap(), vec![r0, r1]);
+  }
+}
@@ -0,0 +1,186 @@
+use std::{collections::VecDeque, sync::Mutex};
+use serde::Deserialize;
+use tokio::sync::Notify;
+use crate::reading::Reading;
+
+/// What to do when a reading arrives while the queue is full.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+  /// Drop the oldest reading in the queue.
+  #[default]
+  DropOldest,
+  /// Keep only the latest reading. The queued readings are replaced by the new one when the queue is full.
+  KeepLatest,
+  /// Wait until the subscriber takes a reading. This stalls the acquisition and the other subscribers.
+  Block,
+}
+
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct QueueConfig {
+  pub capacity: usize,
+  pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueConfig {
+  fn default() -> Self {
+    Self { capacity: DEFAULT_CAPACITY, overflow: OverflowPolicy::default() }
+  }
+}
+
+struct State {
+  buf: VecDeque<Reading>,
+  closed: bool,
+  dropped: u64,
+}
+
+/// Bounded queue between the pipeline and a subscriber.
+pub struct Queue {
+  config: QueueConfig,
+  state: Mutex<State>,
+  readable: Notify,
+  writable: Notify,
+}
+
+impl Queue {
+  pub fn new(config: QueueConfig) -> Self {
+    Self {
+      config: QueueConfig { capacity: config.capacity.max(1), ..config },
+      state: Mutex::new(State { buf: VecDeque::new(), closed: false, dropped: 0 }),
+      readable: Notify::new(),
+      writable: Notify::new(),
+    }
+  }
+
+  pub async fn push(&self, reading: Reading) {
+    loop {
+      let writable = self.writable.notified();
+      {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+          return;
+        }
+        match self.config.overflow {
+          OverflowPolicy::DropOldest => {
+            if self.config.capacity <= state.buf.len() {
+              state.buf.pop_front();
+              state.dropped += 1;
+            }
+          }
+          OverflowPolicy::KeepLatest => {
+            if self.config.capacity <= state.buf.len() {
+              state.dropped += state.buf.len() as u64;
+              state.buf.clear();
+            }
+          }
+          OverflowPolicy::Block => {}
+        }
+        if state.buf.len() < self.config.capacity {
+          state.buf.push_back(reading);
+          self.readable.notify_one();
+          return;
+        }
+      }
+      writable.await;
+    }
+  }
+
+  /// Take the oldest reading. None is returned after the queue is closed and all the readings are taken.
+  pub async fn recv(&self) -> Option<Reading> {
+    loop {
+      let readable = self.readable.notified();
+      {
+        let mut state = self.state.lock().unwrap();
+        if let Some(reading) = state.buf.pop_front() {
+          self.writable.notify_one();
+          return Some(reading);
+        }
+        if state.closed {
+          return None;
+        }
+      }
+      readable.await;
+    }
+  }
+
+  pub fn close(&self) {
+    self.state.lock().unwrap().closed = true;
+    self.readable.notify_one();
+    self.writable.notify_one();
+  }
+
+  /// Number of the readings dropped so far.
+  pub fn dropped(&self) -> u64 {
+    self.state.lock().unwrap().dropped
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, time::Duration};
+  use crate::reading::Reading;
+  use super::{OverflowPolicy, Queue, QueueConfig};
+
+  fn readings() -> Vec<Reading> {
+    [b"00001;80:", b"00002;80:", b"00003;80:"].iter().map(|b| Reading::parse_frame(*b)).collect()
+  }
+
+  #[tokio::test]
+  async fn drop_oldest() {
+    let q = Queue::new(QueueConfig { capacity: 2, overflow: OverflowPolicy::DropOldest });
+    let r = readings();
+    for reading in r.iter() {
+      q.push(reading.clone()).await;
+    }
+    q.close();
+    assert_eq!(q.recv().await, Some(r[1].clone()));
+    assert_eq!(q.recv().await, Some(r[2].clone()));
+    assert_eq!(q.recv().await, None);
+    assert_eq!(q.dropped(), 1);
+  }
+
+  #[tokio::test]
+  async fn keep_latest() {
+    let q = Queue::new(QueueConfig { capacity: 2, overflow: OverflowPolicy::KeepLatest });
+    let r = readings();
+    for reading in r.iter() {
+      q.push(reading.clone()).await;
+    }
+    q.close();
+    assert_eq!(q.recv().await, Some(r[2].clone()));
+    assert_eq!(q.recv().await, None);
+    assert_eq!(q.dropped(), 2);
+
+    let q = Queue::new(QueueConfig { capacity: 4, overflow: OverflowPolicy::KeepLatest });
+    for reading in r.iter() {
+      q.push(reading.clone()).await;
+    }
+    q.close();
+    for reading in r.iter() {
+      assert_eq!(q.recv().await, Some(reading.clone()));
+    }
+    assert_eq!(q.recv().await, None);
+    assert_eq!(q.dropped(), 0);
+  }
+
+  #[tokio::test]
+  async fn block() {
+    let q = Arc::new(Queue::new(QueueConfig { capacity: 1, overflow: OverflowPolicy::Block }));
+    let r = readings();
+    q.push(r[0].clone()).await;
+    let producer = {
+      let q = q.clone();
+      let r = r.clone();
+      tokio::spawn(async move { q.push(r[1].clone()).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!producer.is_finished());
+    assert_eq!(q.recv().await, Some(r[0].clone()));
+    producer.await.unwrap();
+    assert_eq!(q.recv().await, Some(r[1].clone()));
+    assert_eq!(q.dropped(), 0);
+  }
+}
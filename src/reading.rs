@@ -0,0 +1,153 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::{calibration::Calibration, scaling::Channel};
+
+/// A reading received from the meter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+  pub raw: es51986::Output,
+  pub value: Option<es51986::OutputValue>,
+  /// Correction by the calibration table.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub calibration: Option<Calibration>,
+  /// Difference from the reference in REL mode.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rel: Option<Relative>,
+  /// Names of the alarms active at this reading.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub alarms: Vec<String>,
+  /// The readings settled at this reading in capture mode.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub captured: bool,
+  /// Smoothed value in the base unit when the smoothing is enabled.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub smoothed: Option<f64>,
+  /// Values derived by the scales.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub channels: Vec<Channel>,
+  /// Summary of the time window ending at this reading when the readings are aggregated.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub window: Option<Window>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relative {
+  /// Reference value in the base unit.
+  pub reference: f64,
+  /// normalized value - reference.
+  pub delta: f64,
+  /// delta / reference in percent. None if the reference is 0.
+  pub percent: Option<f64>,
+}
+
+/// Summary of the readings in a time window. The values are in the base unit. The readings without value are not counted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+  /// Time covered by the window in seconds. Shorter than the configured length when the window is closed early.
+  pub duration: f64,
+  pub count: u64,
+  pub min: Option<f64>,
+  pub max: Option<f64>,
+  pub mean: Option<f64>,
+}
+
+pub fn prefix_multiplier(prefix: &es51986::PrefixUnit) -> f64 {
+  match prefix {
+    es51986::PrefixUnit::Mega => 1e6,
+    es51986::PrefixUnit::Kilo => 1e3,
+    es51986::PrefixUnit::None => 1.0,
+    es51986::PrefixUnit::Millis => 1e-3,
+    es51986::PrefixUnit::Micro => 1e-6,
+    es51986::PrefixUnit::Nano => 1e-9,
+  }
+}
+
+impl Reading {
+  pub fn new(raw: es51986::Output) -> Self {
+    Self { value: raw.get_value(), raw, calibration: None, rel: None, alarms: vec![], captured: false, smoothed: None, channels: vec![], window: None }
+  }
+
+  /// The value in the base unit (without prefix) with sign applied, corrected by the calibration table.
+  /// None if the reading has no value or overflows.
+  pub fn normalized(&self) -> Option<f64> {
+    match &self.calibration {
+      Some(c) => Some(c.value),
+      None => self.measured(),
+    }
+  }
+
+  /// The value in the base unit as measured, without the calibration.
+  pub fn measured(&self) -> Option<f64> {
+    if self.raw.status.is_overflow {
+      return None;
+    }
+    let value = self.value.as_ref()?;
+    let digits: f64 = value.digits.parse().ok()?;
+    let sign = if self.raw.status.sign.clone().is_minus() { -1.0 } else { 1.0 };
+    Some(sign * digits * prefix_multiplier(&value.value_unit.prefix_unit))
+  }
+
+  pub fn base_unit(&self) -> Option<&es51986::BaseUnit> {
+    self.value.as_ref().map(|v| &v.value_unit.base_unit)
+  }
+}
+
+#[cfg(test)]
+impl Reading {
+  /// The reading of a frame sent by the meter, such as b"109853802". Panics if the frame is invalid.
+  pub fn parse_frame(frame: &[u8]) -> Self {
+    Self::new(es51986::Output::parse(frame).unwrap())
+  }
+}
+
+/// Parse the value with an optional SI prefix such as "4.7k", "100m" or "2.2u".
+pub fn parse_quantity(s: &str) -> Result<f64, String> {
+  let s = s.trim();
+  let (number, multiplier) = match s.char_indices().last() {
+    Some((i, 'M')) => (&s[..i], 1e6),
+    Some((i, 'k')) => (&s[..i], 1e3),
+    Some((i, 'm')) => (&s[..i], 1e-3),
+    Some((i, 'u')) | Some((i, 'µ')) => (&s[..i], 1e-6),
+    Some((i, 'n')) => (&s[..i], 1e-9),
+    _ => (s, 1.0),
+  };
+  number.parse::<f64>().map(|n| n * multiplier).map_err(|_| format!("Invalid value '{}'.", s))
+}
+
+/// Deserialize a number or a string with an SI prefix (see parse_quantity).
+pub fn deserialize_quantity<'de, D: Deserializer<'de>>(d: D) -> Result<f64, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Quantity {
+    Number(f64),
+    Text(String),
+  }
+  match Quantity::deserialize(d)? {
+    Quantity::Number(n) => Ok(n),
+    Quantity::Text(s) => parse_quantity(&s).map_err(serde::de::Error::custom),
+  }
+}
+
+/// Deserialize an optional quantity. Use with `#[serde(default)]` so that a missing key is None.
+pub fn deserialize_optional_quantity<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+  deserialize_quantity(d).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_quantity, Reading};
+
+  #[test]
+  fn normalized() {
+    assert_eq!(Reading::parse_frame(b"109853802").normalized(), Some(985.0));
+    assert_eq!(Reading::parse_frame(b"01234;<0:").normalized(), Some(-1.234));
+    assert_eq!(Reading::parse_frame(b"560003902").normalized(), None);
+  }
+
+  #[test]
+  fn quantity() {
+    assert_eq!(parse_quantity("4.7k"), Ok(4700.0));
+    assert_eq!(parse_quantity("-100m"), Ok(-0.1));
+    assert_eq!(parse_quantity("12"), Ok(12.0));
+    assert!(parse_quantity("1x").is_err());
+  }
+}
@@ -0,0 +1,136 @@
+use std::{fmt, fs::{self, File}, io::{BufWriter, Cursor}, path::PathBuf, time::SystemTime};
+use rodio::{source::UniformSourceIterator, Source};
+use serde::Deserialize;
+
+/// Where the synthesized speech is written.
+///
+/// ```toml
+/// speech-recording = { directory = "speech", track = "session.wav", playback = false }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RecordingConfig {
+  /// Directory to write each utterance as a WAV file named with the time.
+  pub directory: Option<PathBuf>,
+  /// WAV file to write all the utterances of the session in order.
+  pub track: Option<PathBuf>,
+  /// Also play the speech on the audio device.
+  pub playback: bool,
+}
+
+impl Default for RecordingConfig {
+  fn default() -> Self {
+    Self { directory: None, track: None, playback: true }
+  }
+}
+
+impl RecordingConfig {
+  pub fn records(&self) -> bool {
+    self.directory.is_some() || self.track.is_some()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordingErr {
+  Io { path: PathBuf, msg: String },
+  Decode(String),
+}
+
+impl fmt::Display for RecordingErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RecordingErr::Io { path, msg } => write!(f, "Cannot write speech to {}: {}", path.display(), msg),
+      RecordingErr::Decode(msg) => write!(f, "Cannot decode speech: {}", msg),
+    }
+  }
+}
+
+/// Writes the synthesized speech to WAV files.
+pub struct SpeechRecorder {
+  directory: Option<PathBuf>,
+  track_path: Option<PathBuf>,
+  /// Created with the format of the first utterance. The following ones are converted to it.
+  track: Option<hound::WavWriter<BufWriter<File>>>,
+}
+
+impl SpeechRecorder {
+  pub fn new(config: &RecordingConfig) -> Self {
+    Self { directory: config.directory.clone(), track_path: config.track.clone(), track: None }
+  }
+
+  /// Write the utterance synthesized at the time.
+  pub fn record(&mut self, wav: &[u8], at: SystemTime) -> Result<(), RecordingErr> {
+    if let Some(dir) = &self.directory {
+      let path = dir.join(file_name(at));
+      let io_err = |e: std::io::Error| RecordingErr::Io { path: path.clone(), msg: e.to_string() };
+      fs::create_dir_all(dir).map_err(io_err)?;
+      fs::write(&path, wav).map_err(io_err)?;
+    }
+    if let Some(path) = &self.track_path {
+      self.append_track(path.clone(), wav)?;
+    }
+    Ok(())
+  }
+
+  fn append_track(&mut self, path: PathBuf, wav: &[u8]) -> Result<(), RecordingErr> {
+    let source = rodio::Decoder::new_wav(Cursor::new(wav.to_vec())).map_err(|e| RecordingErr::Decode(e.to_string()))?;
+    let wav_err = |e: hound::Error| RecordingErr::Io { path: path.clone(), msg: e.to_string() };
+    if self.track.is_none() {
+      let spec = hound::WavSpec { channels: source.channels(), sample_rate: source.sample_rate(), bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+      if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| RecordingErr::Io { path: path.clone(), msg: e.to_string() })?;
+      }
+      self.track = Some(hound::WavWriter::create(&path, spec).map_err(wav_err)?);
+    }
+    let track = self.track.as_mut().unwrap();
+    let spec = track.spec();
+    for sample in UniformSourceIterator::<_, i16>::new(source, spec.channels, spec.sample_rate) {
+      track.write_sample(sample).map_err(wav_err)?;
+    }
+    // Update the header so that the track can be played even if the session is killed.
+    track.flush().map_err(wav_err)
+  }
+}
+
+/// Name of the WAV file of the utterance, e.g. 2026-10-18T06-30-12.345Z.wav.
+pub fn file_name(at: SystemTime) -> String {
+  format!("{}.wav", humantime::format_rfc3339_millis(at).to_string().replace(':', "-"))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{io::Cursor, time::{Duration, SystemTime}};
+  use super::{file_name, RecordingConfig, SpeechRecorder};
+
+  fn wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+    let mut buf = Cursor::new(vec![]);
+    let spec = hound::WavSpec { channels: 1, sample_rate, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut w = hound::WavWriter::new(&mut buf, spec).unwrap();
+    for s in samples {
+      w.write_sample(*s).unwrap();
+    }
+    w.finalize().unwrap();
+    buf.into_inner()
+  }
+
+  #[test]
+  fn name() {
+    let at = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    assert_eq!(file_name(at), "2023-11-14T22-13-20.123Z.wav");
+  }
+
+  #[test]
+  fn record() {
+    let dir = std::env::temp_dir().join(format!("m6000m-recording-{}", std::process::id()));
+    let config = RecordingConfig { directory: Some(dir.join("utterances")), track: Some(dir.join("session.wav")), playback: false };
+    let mut recorder = SpeechRecorder::new(&config);
+    let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+    recorder.record(&wav(8000, &[1, 2, 3, 4]), at).unwrap();
+    recorder.record(&wav(8000, &[5, 6]), at + Duration::from_secs(1)).unwrap();
+    assert_eq!(std::fs::read(dir.join("utterances").join(file_name(at))).unwrap(), wav(8000, &[1, 2, 3, 4]));
+
+    let samples: Vec<i16> = hound::WavReader::open(dir.join("session.wav")).unwrap().samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, vec![1, 2, 3, 4, 5, 6]);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,100 @@
+use crate::reading::{Reading, Relative};
+
+/// REL mode. Reports the difference from the reference value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rel {
+  reference: Option<f64>,
+  /// The reference applies only to the readings of this unit. Applies to any readings if None.
+  unit: Option<es51986::BaseUnit>,
+}
+
+impl Rel {
+  pub fn new(reference: Option<f64>) -> Self {
+    Self { reference, unit: None }
+  }
+
+  /// Set the reference value in the base unit. It applies to the readings of any unit.
+  pub fn set_reference(&mut self, reference: f64) {
+    self.reference = Some(reference);
+    self.unit = None;
+  }
+
+  /// Use the reading as the reference. Returns false if the reading has no value.
+  pub fn capture(&mut self, reading: &Reading) -> bool {
+    match reading.normalized() {
+      Some(value) => {
+        self.reference = Some(value);
+        self.unit = reading.base_unit().cloned();
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.reference = None;
+    self.unit = None;
+  }
+
+  pub fn reference(&self) -> Option<f64> {
+    self.reference
+  }
+
+  /// Fill the rel field of the reading.
+  pub fn apply(&self, reading: &mut Reading) {
+    reading.rel = None;
+    let Some(reference) = self.reference else { return };
+    if self.unit.is_some() && self.unit.as_ref() != reading.base_unit() {
+      return;
+    }
+    if let Some(value) = reading.normalized() {
+      let delta = value - reference;
+      reading.rel = Some(Relative {
+        reference,
+        delta,
+        percent: if reference == 0.0 { None } else { Some(delta / reference.abs() * 100.0) },
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::reading::{Reading, Relative};
+  use super::Rel;
+
+  #[test]
+  fn captured_reference() {
+    let mut rel = Rel::default();
+    let mut r = Reading::parse_frame(b"110003802");
+    rel.apply(&mut r);
+    assert_eq!(r.rel, None);
+
+    assert!(rel.capture(&r));
+    let mut r = Reading::parse_frame(b"110203802");
+    rel.apply(&mut r);
+    assert_eq!(r.rel, Some(Relative { reference: 1000.0, delta: 20.0, percent: Some(2.0) }));
+
+    // Different unit.
+    let mut r = Reading::parse_frame(b"01000;80:");
+    rel.apply(&mut r);
+    assert_eq!(r.rel, None);
+
+    rel.clear();
+    let mut r = Reading::parse_frame(b"110203802");
+    rel.apply(&mut r);
+    assert_eq!(r.rel, None);
+  }
+
+  #[test]
+  fn given_reference() {
+    let mut rel = Rel::new(Some(0.0));
+    let mut r = Reading::parse_frame(b"01000;80:");
+    rel.apply(&mut r);
+    assert_eq!(r.rel, Some(Relative { reference: 0.0, delta: 1.0, percent: None }));
+
+    rel.set_reference(2.0);
+    rel.apply(&mut r);
+    assert_eq!(r.rel, Some(Relative { reference: 2.0, delta: -1.0, percent: Some(-50.0) }));
+  }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::reading::{self, Reading};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Mapping {
+  /// derived = value * gain + offset.
+  Linear {
+    #[serde(default = "gain_default")]
+    gain: f64,
+    #[serde(default, deserialize_with = "reading::deserialize_quantity")]
+    offset: f64,
+  },
+  /// Piecewise-linear interpolation between the points of [value, derived]. Extrapolated with the first and the last
+  /// segments outside the points. The points are sorted by the value when loaded.
+  Table {
+    #[serde(deserialize_with = "deserialize_points")]
+    points: Vec<[f64; 2]>,
+  },
+}
+
+fn gain_default() -> f64 {
+  1.0
+}
+
+/// Sort the points of a table by the value. At least one point is needed and the numbers must be finite.
+pub fn sort_points(mut points: Vec<[f64; 2]>) -> Result<Vec<[f64; 2]>, String> {
+  if points.is_empty() {
+    return Err("The table needs at least one point.".to_owned());
+  }
+  if let Some(p) = points.iter().find(|p| !p[0].is_finite() || !p[1].is_finite()) {
+    return Err(format!("Invalid point [{}, {}] in the table.", p[0], p[1]));
+  }
+  points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+  Ok(points)
+}
+
+fn deserialize_points<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<[f64; 2]>, D::Error> {
+  sort_points(Vec::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+impl Mapping {
+  /// The points of a table are expected sorted (see sort_points).
+  pub fn map(&self, value: f64) -> Option<f64> {
+    match self {
+      Mapping::Linear { gain, offset } => Some(value * gain + offset),
+      Mapping::Table { points } => {
+        match points.len() {
+          0 => None,
+          1 => Some(points[0][1]),
+          n => {
+            let i = points.iter().position(|p| value < p[0]).unwrap_or(n).clamp(1, n - 1);
+            let ([x0, y0], [x1, y1]) = (points[i - 1], points[i]);
+            if x0 == x1 {
+              return Some(y0);
+            }
+            Some(y0 + (value - x0) * (y1 - y0) / (x1 - x0))
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Scaling of the value to another physical quantity. The value is in the base unit (V, A, Ohm, Hz, F).
+///
+/// ```toml
+/// [[scales]]
+/// name = "clamp"
+/// function = "Voltage"
+/// unit = "A"
+/// type = "linear"
+/// gain = 100.0
+///
+/// [[scales]]
+/// name = "thermocouple"
+/// function = "Voltage"
+/// unit = "°C"
+/// type = "table"
+/// points = [[0.0, 0.0], [0.25, 25.0], [1.0, 100.0]]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScaleRule {
+  pub name: String,
+  /// The scale applies only to this function. Applies to any function if omitted.
+  #[serde(default)]
+  pub function: Option<es51986::Function>,
+  /// The scale applies only to the readings of AC (true) or DC (false). Applies to both if omitted.
+  #[serde(default)]
+  pub ac: Option<bool>,
+  /// Unit of the derived value.
+  pub unit: String,
+  #[serde(flatten)]
+  pub mapping: Mapping,
+}
+
+/// A value derived from the reading by a scale.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+  pub name: String,
+  pub value: f64,
+  pub unit: String,
+}
+
+/// Fill the channels of the reading by the scales that apply to it.
+pub fn apply(scales: &[ScaleRule], reading: &mut Reading) {
+  reading.channels.clear();
+  let Some(value) = reading.normalized() else { return };
+  for s in scales {
+    if s.function.as_ref().map(|f| f != &reading.raw.function).unwrap_or(false) {
+      continue;
+    }
+    if s.ac.map(|ac| if ac { !reading.raw.option2.is_ac } else { !reading.raw.option2.is_dc }).unwrap_or(false) {
+      continue;
+    }
+    if let Some(derived) = s.mapping.map(value) {
+      reading.channels.push(Channel { name: s.name.clone(), value: derived, unit: s.unit.clone() });
+    }
+  }
+}
+
+/// Format the channels into a single field such as "clamp=1.5A;temperature=25°C".
+pub fn format_channels(channels: &[Channel]) -> String {
+  channels.iter().map(|c| format!("{}={}{}", c.name, c.value, c.unit)).collect::<Vec<_>>().join(";")
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::reading::Reading;
+  use super::{apply, format_channels, Mapping, ScaleRule};
+
+  #[test]
+  fn table() {
+    let m: Mapping = toml::from_str("type = \"table\"\npoints = [[1.0, 100.0], [0.0, 0.0], [0.25, 25.0]]").unwrap();
+    assert_eq!(m, Mapping::Table { points: vec![[0.0, 0.0], [0.25, 25.0], [1.0, 100.0]] });
+    assert_eq!(m.map(0.125), Some(12.5));
+    assert_eq!(m.map(0.625), Some(62.5));
+    assert_eq!(m.map(1.5), Some(150.0));
+    assert_eq!(m.map(-0.1), Some(-10.0));
+    assert_eq!(Mapping::Table { points: vec![[0.5, 7.0]] }.map(1.0), Some(7.0));
+    assert!(toml::from_str::<Mapping>("type = \"table\"\npoints = []").is_err());
+    assert!(toml::from_str::<Mapping>("type = \"table\"\npoints = [[0.0, nan]]").is_err());
+  }
+
+  #[test]
+  fn channels() {
+    let scales = vec![
+      ScaleRule {
+        name: "clamp".to_owned(), function: Some(es51986::Function::Voltage), ac: None, unit: "A".to_owned(),
+        mapping: Mapping::Linear { gain: 100.0, offset: 0.0 },
+      },
+      ScaleRule {
+        name: "sensor".to_owned(), function: Some(es51986::Function::Ohm), ac: None, unit: "K".to_owned(),
+        mapping: Mapping::Linear { gain: 1.0, offset: 0.0 },
+      },
+    ];
+    let mut r = Reading::parse_frame(b"01234;<0:");
+    apply(&scales, &mut r);
+    assert_eq!(r.channels.len(), 1);
+    assert!((r.channels[0].value + 123.4).abs() < 1e-9);
+    assert_eq!(r.channels[0].unit, "A");
+
+    let mut r = Reading::parse_frame(b"560003902");
+    apply(&scales, &mut r);
+    assert_eq!(format_channels(&r.channels), "");
+  }
+}
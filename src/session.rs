@@ -0,0 +1,207 @@
+use std::{fs::{File, OpenOptions}, io::{BufWriter, Write}, path::PathBuf, time::{Duration, Instant}};
+use log::{error, info, warn};
+use m6000m_rs::{alarm::{AlarmEventKind, Alarms}, audio::Sound, binning::Binning, calibration::CalibrationTable, reading::Reading, rel::Rel, scaling::{self, ScaleRule}, smoothing::Smoother, speech::SpeechTrigger, tts::TtsConfig, stable::StableDetector, stats::{Statistics, Summary}};
+use crate::{arg::Args, control::Command, notifier::{Beep, Notifier}, device::pick_audio_output_device, tts_backend, AppErr};
+
+/// Processing applied to each reading before it is delivered to the outputs, and the commands typed while measuring.
+pub struct Session {
+    stats: Option<Statistics>,
+    calibration: Option<CalibrationTable>,
+    smoother: Option<Smoother>,
+    scales: Vec<ScaleRule>,
+    rel: Rel,
+    alarms: Alarms,
+    capture: Option<StableDetector>,
+    binning: Option<Binning>,
+    binning_sound: Sound,
+    part_log: Option<(PathBuf, BufWriter<File>)>,
+    notifier: Option<Notifier>,
+    /// Word appended to the message of a cleared alarm.
+    cleared: &'static str,
+    speech_trigger: SpeechTrigger,
+    latest: Option<Reading>,
+}
+
+impl Session {
+    /// The announcements are spoken with the voice.
+    pub fn new(args: &Args, voice: Option<TtsConfig>, speech_trigger: SpeechTrigger) -> Result<Self, AppErr> {
+        let alarms = Alarms::new(args.alarms.clone().unwrap_or_default());
+        let calibration = match &args.calibration {
+            Some(path) => {
+                let table = CalibrationTable::load(path).map_err(|e| AppErr::ConfigError(e.to_string()))?;
+                info!("Calibration '{}' (sha256 {}) is loaded.", path.display(), table.hash());
+                Some(table)
+            }
+            None => None,
+        };
+        let binning = args.binning();
+        let needs_sound = alarms.rules().any(|r| r.sound != Sound::None)
+            || binning.as_ref().map(|b| b.sound != Sound::None).unwrap_or(false);
+        let notifier: Option<Notifier> = if needs_sound {
+            // Nothing is played with --no-speech-playback so that no audio hardware is needed. The measurement goes on
+            // without sound when the device is not available.
+            let device = if args.recording().playback {
+                pick_audio_output_device(&args.audio_output_device_name)
+                    .inspect_err(|e| warn!("Alarms and binning results are not sounded: {}", e))
+                    .ok()
+            } else {
+                None
+            };
+            Some(Notifier::new(device, voice.map(|tts| tts_backend(&tts, args))))
+        } else {
+            None
+        };
+        let part_log = match binning.as_ref().and_then(|b| b.log.clone()) {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(&path)
+                    .map_err(|e| AppErr::OutputError(format!("Cannot open '{}': {}", path.display(), e)))?;
+                let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+                let mut w = BufWriter::new(file);
+                if is_empty {
+                    let _ = writeln!(w, "number,value,deviation,bin");
+                }
+                Some((path, w))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            stats: if args.stats_enabled() { Some(Statistics::new()) } else { None },
+            calibration,
+            smoother: args.smoothing().map(Smoother::new),
+            scales: args.scales.clone().unwrap_or_default(),
+            rel: Rel::new(args.rel),
+            capture: args.capture().map(StableDetector::new),
+            alarms,
+            binning_sound: binning.as_ref().map(|b| b.sound.clone()).unwrap_or_default(),
+            binning: binning.map(|b| Binning::new(b.binning)),
+            part_log,
+            notifier,
+            cleared: args.voice_locale().phrases().cleared,
+            speech_trigger,
+            latest: None,
+        })
+    }
+
+    pub fn on_reading(&mut self, reading: &mut Reading) {
+        let now = Instant::now();
+        if let Some(calibration) = &self.calibration {
+            calibration.apply(reading);
+        }
+        if let Some(smoother) = &mut self.smoother {
+            smoother.apply(reading);
+        }
+        scaling::apply(&self.scales, reading);
+        self.rel.apply(reading);
+        for event in self.alarms.evaluate(reading, now) {
+            warn!("Alarm '{}' {:?} (value = {}).", event.name, event.kind, event.value);
+            eprintln!("{}", serde_json::json!({ "alarm": event }));
+            if let (Some(notifier), Some(rule)) = (&self.notifier, self.alarms.rules().find(|r| r.name == event.name)) {
+                match event.kind {
+                    AlarmEventKind::Raised => notifier.announce(
+                        rule.sound.clone(), rule.message().to_owned(), Beep { frequency: 880.0, duration: Duration::from_millis(150), count: 3 }
+                    ),
+                    AlarmEventKind::Cleared => notifier.announce(
+                        rule.sound.clone(), format!("{} {}", rule.message(), self.cleared), Beep { frequency: 440.0, duration: Duration::from_millis(150), count: 1 }
+                    ),
+                }
+            }
+        }
+        if let Some(capture) = &mut self.capture {
+            reading.captured = capture.update(reading, now);
+        }
+        if let Some(part) = self.binning.as_mut().and_then(|b| b.on_reading(reading, now)) {
+            eprintln!("{}", serde_json::json!({ "part": part }));
+            if let Some((path, w)) = &mut self.part_log {
+                if let Err(err) = writeln!(w, "{},{},{},{}", part.number, part.value, part.deviation, part.bin).and_then(|_| w.flush()) {
+                    error!("Cannot write to {}: {:?}", path.display(), err);
+                }
+            }
+            if let Some(notifier) = &self.notifier {
+                let beep = match part.bin_index {
+                    Some(i) => Beep { frequency: 1000.0, duration: Duration::from_millis(100), count: i + 1 },
+                    None => Beep { frequency: 300.0, duration: Duration::from_millis(600), count: 1 },
+                };
+                notifier.announce(self.binning_sound.clone(), part.bin.clone(), beep);
+            }
+        }
+        if let Some(stats) = &mut self.stats {
+            if let Some(finished) = stats.add(reading) {
+                report_statistics(Some(finished));
+            }
+        }
+        self.latest = Some(reading.clone());
+    }
+
+    pub fn on_command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Statistics => {
+                eprintln!("{}", self.statistics_line());
+                self.report_binning();
+            }
+            Command::CaptureReference => match &self.latest {
+                Some(reading) if self.rel.capture(reading) => eprintln!("REL mode. Reference: {}", self.rel.reference().unwrap()),
+                _ => warn!("No reading with value to use as the reference."),
+            },
+            Command::ClearReference => {
+                self.rel.clear();
+                eprintln!("REL mode is cleared.");
+            }
+            Command::Speak => self.speech_trigger.request(),
+        }
+    }
+
+    /// The statistics so far, printed by the 's' command.
+    fn statistics_line(&self) -> String {
+        match &self.stats {
+            Some(stats) => statistics_line(stats.summary()),
+            None => "Statistics are disabled (use --stats).".to_owned(),
+        }
+    }
+
+    /// Periodic report.
+    pub fn on_tick(&mut self) {
+        report_statistics(self.stats.as_ref().and_then(|s| s.summary()));
+    }
+
+    pub fn finish(self) {
+        if let Some(summary) = self.stats.as_ref().and_then(|s| s.summary()) {
+            report_statistics(Some(summary));
+        }
+        self.report_binning();
+    }
+
+    fn report_binning(&self) {
+        if let Some(binning) = &self.binning {
+            let counts: serde_json::Map<String, serde_json::Value> = binning.counts().into_iter().map(|(name, count)| (name, count.into())).collect();
+            eprintln!("{}", serde_json::json!({ "binning": counts }));
+        }
+    }
+}
+
+fn report_statistics(summary: Option<Summary>) {
+    eprintln!("{}", statistics_line(summary));
+}
+
+fn statistics_line(summary: Option<Summary>) -> String {
+    match summary {
+        Some(summary) => serde_json::json!({ "statistics": summary }).to_string(),
+        None => "No statistics yet.".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use m6000m_rs::speech::SpeechTrigger;
+    use crate::arg::Args;
+    use super::Session;
+
+    #[test]
+    fn statistics_disabled() {
+        let session = Session::new(&Args::default(), None, SpeechTrigger::default()).unwrap();
+        assert_eq!(session.statistics_line(), "Statistics are disabled (use --stats).");
+
+        let session = Session::new(&Args { stats: true, ..Default::default() }, None, SpeechTrigger::default()).unwrap();
+        assert_eq!(session.statistics_line(), "No statistics yet.");
+    }
+}
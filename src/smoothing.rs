@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::reading::Reading;
+
+#[derive(ValueEnum, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmoothingMethod {
+  /// Simple moving average of the last N values.
+  Sma,
+  /// Exponential moving average.
+  Ema,
+  /// Median of the last N values. Rejects spikes.
+  Median,
+}
+
+/// Smoothing of the values.
+///
+/// ```toml
+/// [smoothing]
+/// method = "median"
+/// length = 5
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SmoothingConfig {
+  pub method: SmoothingMethod,
+  /// Number of the values for sma and median.
+  #[serde(default = "length_default")]
+  pub length: usize,
+  /// Weight of the new value for ema (0 < alpha <= 1).
+  #[serde(default = "alpha_default")]
+  pub alpha: f64,
+}
+
+pub fn length_default() -> usize {
+  5
+}
+
+pub fn alpha_default() -> f64 {
+  0.2
+}
+
+/// Fills the smoothed value of the readings. Restarts when the function or the base unit changes.
+/// The readings without value are not smoothed and do not affect the following ones.
+pub struct Smoother {
+  config: SmoothingConfig,
+  key: Option<(es51986::Function, es51986::BaseUnit)>,
+  values: VecDeque<f64>,
+  ema: Option<f64>,
+}
+
+impl Smoother {
+  pub fn new(config: SmoothingConfig) -> Self {
+    Self { config, key: None, values: VecDeque::new(), ema: None }
+  }
+
+  pub fn apply(&mut self, reading: &mut Reading) {
+    reading.smoothed = None;
+    let (Some(unit), Some(value)) = (reading.base_unit(), reading.normalized()) else { return };
+    let key = (reading.raw.function.clone(), unit.clone());
+    if self.key.as_ref() != Some(&key) {
+      self.key = Some(key);
+      self.values.clear();
+      self.ema = None;
+    }
+
+    let length = self.config.length.max(1);
+    self.values.push_back(value);
+    while length < self.values.len() {
+      self.values.pop_front();
+    }
+    reading.smoothed = Some(match self.config.method {
+      SmoothingMethod::Sma => self.values.iter().sum::<f64>() / self.values.len() as f64,
+      SmoothingMethod::Ema => {
+        let alpha = self.config.alpha.clamp(f64::MIN_POSITIVE, 1.0);
+        *self.ema.insert(self.ema.map(|e| e + alpha * (value - e)).unwrap_or(value))
+      }
+      SmoothingMethod::Median => {
+        let mut sorted: Vec<f64> = self.values.iter().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len();
+        if n % 2 == 1 { sorted[n / 2] } else { (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0 }
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::reading::Reading;
+  use super::{SmoothingConfig, SmoothingMethod, Smoother};
+
+  fn smoothed(s: &mut Smoother, b: &[u8]) -> Option<f64> {
+    let mut r = Reading::parse_frame(b);
+    s.apply(&mut r);
+    r.smoothed
+  }
+
+  fn assert_near(v: Option<f64>, expected: f64) {
+    assert!((v.unwrap() - expected).abs() < 1e-9, "{:?} != {}", v, expected);
+  }
+
+  #[test]
+  fn sma() {
+    let mut s = Smoother::new(SmoothingConfig { method: SmoothingMethod::Sma, length: 3, alpha: 0.2 });
+    assert_near(smoothed(&mut s, b"110003802"), 1000.0);
+    assert_near(smoothed(&mut s, b"110203802"), 1010.0);
+    assert_near(smoothed(&mut s, b"110403802"), 1020.0);
+    assert_near(smoothed(&mut s, b"110603802"), 1040.0);
+    assert_eq!(smoothed(&mut s, b"560003902"), None);
+    assert_near(smoothed(&mut s, b"110003802"), 3100.0 / 3.0);
+    // Restarts when the function changes.
+    assert_near(smoothed(&mut s, b"00002;80:"), 0.002);
+  }
+
+  #[test]
+  fn ema() {
+    let mut s = Smoother::new(SmoothingConfig { method: SmoothingMethod::Ema, length: 5, alpha: 0.5 });
+    assert_near(smoothed(&mut s, b"110003802"), 1000.0);
+    assert_near(smoothed(&mut s, b"110203802"), 1010.0);
+    assert_near(smoothed(&mut s, b"110203802"), 1015.0);
+  }
+
+  #[test]
+  fn median() {
+    let mut s = Smoother::new(SmoothingConfig { method: SmoothingMethod::Median, length: 3, alpha: 0.2 });
+    assert_near(smoothed(&mut s, b"110003802"), 1000.0);
+    assert_near(smoothed(&mut s, b"147003802"), 2850.0);
+    assert_near(smoothed(&mut s, b"110203802"), 1020.0);
+    assert_near(smoothed(&mut s, b"110103802"), 1020.0);
+  }
+}
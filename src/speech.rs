@@ -0,0 +1,185 @@
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+use serde::Deserialize;
+use crate::{filter::{Deadband, DeadbandConfig, Filter}, reading::{self, Reading}, stable::{StableConfig, StableDetector}};
+
+/// When to speak the readings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "on", rename_all = "kebab-case", rename_all_fields = "kebab-case", deny_unknown_fields)]
+pub enum SpeechPolicy {
+  /// When the value changes beyond the deadband from the last spoken one.
+  Change {
+    /// Absolute deadband in the base unit.
+    #[serde(default, deserialize_with = "reading::deserialize_quantity")]
+    deadband: f64,
+    /// Relative deadband in percent.
+    #[serde(default)]
+    deadband_percent: f64,
+  },
+  /// When the readings settle.
+  Stable(StableConfig),
+  /// At this interval (seconds).
+  Interval { seconds: f64 },
+  /// Only when requested (the 'v' command). A struct variant so that the unknown keys are rejected.
+  Demand {},
+}
+
+impl Default for SpeechPolicy {
+  fn default() -> Self {
+    SpeechPolicy::Change { deadband: 0.0, deadband_percent: 0.0 }
+  }
+}
+
+/// Speech scheduling.
+///
+/// ```toml
+/// speech = { on = "stable", tolerance = 0.2, duration = 1.0 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SpeechConfig {
+  #[serde(flatten)]
+  pub policy: SpeechPolicy,
+  /// Speak the same text again. The text same as the last spoken one is skipped if false.
+  #[serde(default)]
+  pub repeat: bool,
+}
+
+/// Request to speak the next reading. Shared between the command reader and the speech outputs. Each clone sees a
+/// request once, so every speech output speaks. A clone sees only the requests made after it is cloned.
+#[derive(Debug, Default)]
+pub struct SpeechTrigger {
+  requests: Arc<AtomicU64>,
+  seen: u64,
+}
+
+impl Clone for SpeechTrigger {
+  fn clone(&self) -> Self {
+    Self { requests: self.requests.clone(), seen: self.requests.load(Ordering::Relaxed) }
+  }
+}
+
+impl SpeechTrigger {
+  pub fn request(&self) {
+    self.requests.fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn take(&mut self) -> bool {
+    let requests = self.requests.load(Ordering::Relaxed);
+    let requested = requests != self.seen;
+    self.seen = requests;
+    requested
+  }
+}
+
+/// Decides which readings to speak.
+pub struct SpeechScheduler {
+  config: SpeechConfig,
+  trigger: SpeechTrigger,
+  deadband: Option<Deadband>,
+  stable: Option<StableDetector>,
+  last_spoken: Option<(String, Instant)>,
+}
+
+impl SpeechScheduler {
+  pub fn new(config: SpeechConfig, trigger: SpeechTrigger) -> Self {
+    let deadband = match &config.policy {
+      SpeechPolicy::Change { deadband, deadband_percent } => {
+        Some(Deadband::new(DeadbandConfig { absolute: *deadband, relative: *deadband_percent, heartbeat: None }))
+      }
+      _ => None,
+    };
+    let stable = match &config.policy {
+      SpeechPolicy::Stable(stable) => Some(StableDetector::new(stable.clone())),
+      _ => None,
+    };
+    Self { config, trigger, deadband, stable, last_spoken: None }
+  }
+
+  /// Returns the text if it is to be spoken.
+  pub fn schedule(&mut self, reading: &Reading, text: String, now: Instant) -> Option<String> {
+    if self.trigger.take() {
+      self.last_spoken = Some((text.clone(), now));
+      return Some(text);
+    }
+    let due = match &self.config.policy {
+      SpeechPolicy::Change { .. } => self.deadband.as_mut().map(|d| !d.apply(reading.clone(), now).is_empty()).unwrap_or(false),
+      SpeechPolicy::Stable(_) => self.stable.as_mut().map(|s| s.update(reading, now)).unwrap_or(false),
+      SpeechPolicy::Interval { seconds } => self.last_spoken.as_ref()
+        .map(|(_, at)| Duration::from_secs_f64(seconds.max(0.0)) <= now.duration_since(*at))
+        .unwrap_or(true),
+      SpeechPolicy::Demand {} => false,
+    };
+    if !due {
+      return None;
+    }
+    if !self.config.repeat && self.last_spoken.as_ref().map(|(last, _)| last == &text).unwrap_or(false) {
+      return None;
+    }
+    self.last_spoken = Some((text.clone(), now));
+    Some(text)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use crate::reading::Reading;
+  use super::{SpeechConfig, SpeechPolicy, SpeechScheduler, SpeechTrigger};
+
+  fn schedule(s: &mut SpeechScheduler, b: &[u8], now: Instant) -> bool {
+    let reading = Reading::parse_frame(b);
+    let text = reading.value.as_ref().map(|v| v.digits.clone()).unwrap_or_default();
+    s.schedule(&reading, text, now).is_some()
+  }
+
+  #[test]
+  fn change() {
+    let policy = SpeechPolicy::Change { deadband: 0.005, deadband_percent: 0.0 };
+    let mut s = SpeechScheduler::new(SpeechConfig { policy, repeat: false }, SpeechTrigger::default());
+    let now = Instant::now();
+    assert!(schedule(&mut s, b"05000;80:", now));
+    assert!(!schedule(&mut s, b"05000;80:", now));
+    assert!(!schedule(&mut s, b"05003;80:", now));
+    assert!(schedule(&mut s, b"05010;80:", now));
+  }
+
+  #[test]
+  fn interval_and_demand() {
+    let trigger = SpeechTrigger::default();
+    let mut s = SpeechScheduler::new(SpeechConfig { policy: SpeechPolicy::Interval { seconds: 2.0 }, repeat: true }, trigger.clone());
+    let now = Instant::now();
+    assert!(schedule(&mut s, b"05000;80:", now));
+    assert!(!schedule(&mut s, b"05000;80:", now + Duration::from_secs(1)));
+    assert!(schedule(&mut s, b"05000;80:", now + Duration::from_secs(2)));
+    trigger.request();
+    assert!(schedule(&mut s, b"05000;80:", now + Duration::from_secs(3)));
+    assert!(!schedule(&mut s, b"05000;80:", now + Duration::from_secs(3)));
+
+    let mut s = SpeechScheduler::new(SpeechConfig { policy: SpeechPolicy::Demand {}, repeat: false }, trigger.clone());
+    assert!(!schedule(&mut s, b"05000;80:", now));
+    trigger.request();
+    assert!(schedule(&mut s, b"05000;80:", now));
+  }
+
+  #[test]
+  fn trigger_shared() {
+    let trigger = SpeechTrigger::default();
+    let demand = || SpeechScheduler::new(SpeechConfig { policy: SpeechPolicy::Demand {}, repeat: true }, trigger.clone());
+    let (mut a, mut b) = (demand(), demand());
+    let now = Instant::now();
+    trigger.request();
+    assert!(schedule(&mut a, b"05000;80:", now));
+    assert!(schedule(&mut b, b"05000;80:", now));
+    assert!(!schedule(&mut a, b"05000;80:", now));
+    assert!(!schedule(&mut b, b"05000;80:", now));
+  }
+
+  #[test]
+  fn stable() {
+    let mut s = SpeechScheduler::new(SpeechConfig { policy: SpeechPolicy::Stable(Default::default()), repeat: false }, SpeechTrigger::default());
+    let now = Instant::now();
+    assert!(!schedule(&mut s, b"05000;80:", now));
+    assert!(schedule(&mut s, b"05000;80:", now + Duration::from_secs(1)));
+    assert!(!schedule(&mut s, b"05000;80:", now + Duration::from_secs(2)));
+  }
+}
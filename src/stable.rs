@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::reading::{self, Reading};
+
+/// Conditions for a reading to be regarded as stable.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct StableConfig {
+  /// Readings are stable when they stay within this percentage of the first one in the window.
+  pub tolerance: f64,
+  /// Absolute tolerance in the base unit. The larger one of tolerance and absolute is used.
+  #[serde(deserialize_with = "reading::deserialize_quantity")]
+  pub absolute: f64,
+  /// Length of the window in seconds.
+  pub duration: f64,
+  /// Readings whose absolute value is below this are treated as open probes (e.g. 0V while probes are apart).
+  #[serde(deserialize_with = "reading::deserialize_quantity")]
+  pub ignore_below: f64,
+}
+
+impl Default for StableConfig {
+  fn default() -> Self {
+    Self { tolerance: 0.5, absolute: 0.0, duration: 0.5, ignore_below: 0.0 }
+  }
+}
+
+impl StableConfig {
+  fn within(&self, reference: f64, value: f64) -> bool {
+    (value - reference).abs() <= self.absolute.max(reference.abs() * self.tolerance / 100.0)
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+  start: Instant,
+  reference: f64,
+  key: (es51986::Function, Option<es51986::BaseUnit>),
+  captured: bool,
+}
+
+/// Detects that the readings settle. Reports once per contact: after a report, the detector is re-armed only when the
+/// probes are apart (overflow, no value or ignore_below) or the function or unit changes, so that a drifting value is
+/// not reported twice. Before a report, a value moving out of the tolerance restarts the window.
+#[derive(Debug, Clone, Default)]
+pub struct StableDetector {
+  config: StableConfig,
+  window: Option<Window>,
+}
+
+impl StableDetector {
+  pub fn new(config: StableConfig) -> Self {
+    Self { config, window: None }
+  }
+
+  /// Returns true when the reading received at now completes a stable window for the first time.
+  pub fn update(&mut self, reading: &Reading, now: Instant) -> bool {
+    let value = match reading.normalized() {
+      Some(value) if self.config.ignore_below <= value.abs() => value,
+      _ => {
+        self.window = None;
+        return false;
+      }
+    };
+    let key = (reading.raw.function.clone(), reading.base_unit().cloned());
+    let window = match &mut self.window {
+      Some(w) if w.key == key && (w.captured || self.config.within(w.reference, value)) => w,
+      _ => self.window.insert(Window { start: now, reference: value, key, captured: false }),
+    };
+    if !window.captured && Duration::from_secs_f64(self.config.duration.max(0.0)) <= now.duration_since(window.start) {
+      window.captured = true;
+      true
+    } else {
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use crate::reading::Reading;
+  use super::{StableConfig, StableDetector};
+
+  #[test]
+  fn capture_once_per_contact() {
+    let mut d = StableDetector::new(StableConfig { tolerance: 1.0, duration: 1.0, ..Default::default() });
+    let t = Instant::now();
+    let ms = |n: u64| t + Duration::from_millis(n);
+    assert!(!d.update(&Reading::parse_frame(b"110003802"), ms(0)));
+    assert!(!d.update(&Reading::parse_frame(b"110053802"), ms(500)));
+    assert!(d.update(&Reading::parse_frame(b"109953802"), ms(1000)));
+    assert!(!d.update(&Reading::parse_frame(b"110003802"), ms(1500)));
+    // Probes apart.
+    assert!(!d.update(&Reading::parse_frame(b"560003902"), ms(2000)));
+    assert!(!d.update(&Reading::parse_frame(b"147003802"), ms(2500)));
+    // Moved out of the tolerance. The window restarts.
+    assert!(!d.update(&Reading::parse_frame(b"147603802"), ms(3000)));
+    assert!(!d.update(&Reading::parse_frame(b"147603802"), ms(3500)));
+    assert!(d.update(&Reading::parse_frame(b"147603802"), ms(4000)));
+  }
+
+  #[test]
+  fn drifting_value() {
+    let mut d = StableDetector::new(StableConfig { tolerance: 1.0, duration: 1.0, ..Default::default() });
+    let t = Instant::now();
+    let ms = |n: u64| t + Duration::from_millis(n);
+    assert!(!d.update(&Reading::parse_frame(b"110003802"), ms(0)));
+    assert!(d.update(&Reading::parse_frame(b"110003802"), ms(1000)));
+    // The value drifts beyond the tolerance while the probes stay on the part.
+    for (i, frame) in [b"110203802", b"110403802", b"110603802", b"110803802"].iter().enumerate() {
+      assert!(!d.update(&Reading::parse_frame(*frame), ms(1500 + 500 * i as u64)));
+    }
+    assert!(!d.update(&Reading::parse_frame(b"110803802"), ms(5000)));
+    // Re-armed by lifting the probes.
+    assert!(!d.update(&Reading::parse_frame(b"560003902"), ms(5500)));
+    assert!(!d.update(&Reading::parse_frame(b"110803802"), ms(6000)));
+    assert!(d.update(&Reading::parse_frame(b"110803802"), ms(7000)));
+  }
+
+  #[test]
+  fn ignore_below() {
+    let mut d = StableDetector::new(StableConfig { duration: 0.0, ignore_below: 0.01, ..Default::default() });
+    let t = Instant::now();
+    assert!(!d.update(&Reading::parse_frame(b"00002;80:"), t));
+    assert!(d.update(&Reading::parse_frame(b"05000;80:"), t));
+  }
+}
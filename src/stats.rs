@@ -0,0 +1,114 @@
+use serde::Serialize;
+use crate::{format, reading::Reading};
+
+/// Statistics of the readings of the same function and unit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+  pub function: es51986::Function,
+  /// Base unit of min, max, mean and stddev.
+  pub unit: String,
+  pub count: u64,
+  pub min: f64,
+  pub max: f64,
+  pub mean: f64,
+  /// Sample standard deviation. 0 if count is 1.
+  pub stddev: f64,
+}
+
+/// Running statistics. Restarts when the function or the base unit of the readings changes.
+/// The readings that have no value (overflow etc.) are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+  key: Option<(es51986::Function, es51986::BaseUnit)>,
+  count: u64,
+  mean: f64,
+  m2: f64,
+  min: f64,
+  max: f64,
+}
+
+impl Statistics {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add the reading. If the function or the unit is changed, the summary of the readings so far is returned.
+  pub fn add(&mut self, reading: &Reading) -> Option<Summary> {
+    let key = (reading.raw.function.clone(), reading.base_unit()?.clone());
+    let value = reading.normalized()?;
+    let finished = if self.key.as_ref() != Some(&key) {
+      let summary = self.summary();
+      self.reset();
+      self.key = Some(key);
+      summary
+    } else {
+      None
+    };
+
+    // Welford's algorithm.
+    self.count += 1;
+    let delta = value - self.mean;
+    self.mean += delta / self.count as f64;
+    self.m2 += delta * (value - self.mean);
+    if self.count == 1 {
+      self.min = value;
+      self.max = value;
+    } else {
+      self.min = self.min.min(value);
+      self.max = self.max.max(value);
+    }
+    finished
+  }
+
+  pub fn summary(&self) -> Option<Summary> {
+    let (function, base_unit) = self.key.as_ref()?;
+    if self.count == 0 {
+      return None;
+    }
+    Some(Summary {
+      function: function.clone(),
+      unit: format::base_unit_symbol(base_unit).to_owned(),
+      count: self.count,
+      min: self.min,
+      max: self.max,
+      mean: self.mean,
+      stddev: if self.count < 2 { 0.0 } else { (self.m2 / (self.count - 1) as f64).sqrt() },
+    })
+  }
+
+  pub fn reset(&mut self) {
+    *self = Self::default();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::reading::Reading;
+  use super::Statistics;
+
+  #[test]
+  fn running_statistics() {
+    let mut stats = Statistics::new();
+    assert_eq!(stats.summary(), None);
+    assert_eq!(stats.add(&Reading::parse_frame(b"02000;80:")), None);
+    assert_eq!(stats.add(&Reading::parse_frame(b"04000;80:")), None);
+    assert_eq!(stats.add(&Reading::parse_frame(b"560003902")), None);
+    assert_eq!(stats.add(&Reading::parse_frame(b"09000;80:")), None);
+
+    let s = stats.summary().unwrap();
+    assert_eq!(s.function, es51986::Function::Voltage);
+    assert_eq!(s.unit, "V");
+    assert_eq!(s.count, 3);
+    assert_eq!(s.min, 2.0);
+    assert_eq!(s.max, 9.0);
+    assert_eq!(s.mean, 5.0);
+    assert!((s.stddev - 13f64.sqrt()).abs() < 1e-9);
+
+    let finished = stats.add(&Reading::parse_frame(b"109853802")).unwrap();
+    assert_eq!(finished, s);
+    let s = stats.summary().unwrap();
+    assert_eq!(s.function, es51986::Function::Ohm);
+    assert_eq!(s.count, 1);
+    assert_eq!(s.stddev, 0.0);
+  }
+}
@@ -0,0 +1,243 @@
+use std::{f32::consts::TAU, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+use rodio::Source;
+use serde::Deserialize;
+use crate::reading::{self, Reading};
+
+/// Resistance below which the continuity tone sounds (ohm).
+pub const DEFAULT_CONTINUITY_THRESHOLD: f64 = 30.0;
+/// Counts of the full scale of the display, used by the pitch tone when the range is not given.
+pub const FULL_SCALE_COUNTS: f64 = 6000.0;
+/// The tone stops when no reading arrives for this duration (e.g. the readings are filtered out).
+pub const TONE_TIMEOUT: Duration = Duration::from_secs(1);
+const SAMPLE_RATE: u32 = 44100;
+
+/// How the readings are turned into a tone.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case", rename_all_fields = "kebab-case", deny_unknown_fields)]
+pub enum ToneConfig {
+  /// Beep while the resistance is below the threshold.
+  Continuity {
+    #[serde(default = "default_threshold", deserialize_with = "reading::deserialize_quantity")]
+    threshold: f64,
+    #[serde(default = "default_beep_frequency")]
+    frequency: f32,
+  },
+  /// Sound a tone whose pitch follows the value. The value from low to high is mapped to min-frequency to
+  /// max-frequency on a logarithmic scale. Without high, the counts on the display are mapped instead.
+  Pitch {
+    #[serde(default, deserialize_with = "reading::deserialize_quantity")]
+    low: f64,
+    #[serde(default, deserialize_with = "reading::deserialize_optional_quantity")]
+    high: Option<f64>,
+    #[serde(default = "default_min_frequency")]
+    min_frequency: f32,
+    #[serde(default = "default_max_frequency")]
+    max_frequency: f32,
+  },
+}
+
+fn default_threshold() -> f64 {
+  DEFAULT_CONTINUITY_THRESHOLD
+}
+
+fn default_beep_frequency() -> f32 {
+  2000.0
+}
+
+fn default_min_frequency() -> f32 {
+  220.0
+}
+
+fn default_max_frequency() -> f32 {
+  1760.0
+}
+
+impl ToneConfig {
+  /// Keys of all the modes including the tag. A flattened ToneConfig takes only these keys from the enclosing table.
+  pub const KEYS: &'static [&'static str] = &["mode", "threshold", "frequency", "low", "high", "min-frequency", "max-frequency"];
+
+  pub fn continuity(threshold: Option<f64>) -> Self {
+    ToneConfig::Continuity { threshold: threshold.unwrap_or(DEFAULT_CONTINUITY_THRESHOLD), frequency: default_beep_frequency() }
+  }
+
+  pub fn pitch(low: Option<f64>, high: Option<f64>) -> Self {
+    ToneConfig::Pitch { low: low.unwrap_or(0.0), high, min_frequency: default_min_frequency(), max_frequency: default_max_frequency() }
+  }
+
+  /// Frequency of the tone for the reading. None for silence.
+  pub fn frequency(&self, reading: &Reading) -> Option<f32> {
+    match self {
+      ToneConfig::Continuity { threshold, frequency } => {
+        (0.0..*threshold).contains(&resistance(reading)?).then_some(*frequency)
+      }
+      ToneConfig::Pitch { low, high, min_frequency, max_frequency } => {
+        let value = reading.normalized()?;
+        let position = match high {
+          Some(high) if high != low => (value - low) / (high - low),
+          Some(_) => return None,
+          None => counts(reading)? / FULL_SCALE_COUNTS,
+        };
+        let position = position.clamp(0.0, 1.0) as f32;
+        Some(min_frequency * (max_frequency / min_frequency).powf(position))
+      }
+    }
+  }
+}
+
+/// Resistance in ohm. The continuity function has no value in the protocol, its display is in 0.1 ohm.
+fn resistance(reading: &Reading) -> Option<f64> {
+  match reading.raw.function {
+    es51986::Function::Ohm => reading.normalized(),
+    es51986::Function::Continuity if !reading.raw.status.is_overflow => {
+      reading.raw.digits.to_value(es51986::DigitRadix::Minus1).parse().ok()
+    }
+    _ => None,
+  }
+}
+
+/// Counts on the display without the decimal point and the sign.
+fn counts(reading: &Reading) -> Option<f64> {
+  let value = reading.value.as_ref()?;
+  value.digits.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse().ok()
+}
+
+#[derive(Debug)]
+struct ToneState {
+  /// Frequency in bits of f32. 0 for silence.
+  frequency: AtomicU32,
+  updated: Mutex<Instant>,
+  stopped: AtomicBool,
+}
+
+/// Controls the tone played by the ToneSource.
+#[derive(Debug, Clone)]
+pub struct ToneControl {
+  state: Arc<ToneState>,
+}
+
+impl Default for ToneControl {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ToneControl {
+  pub fn new() -> Self {
+    Self { state: Arc::new(ToneState { frequency: AtomicU32::new(0), updated: Mutex::new(Instant::now()), stopped: AtomicBool::new(false) }) }
+  }
+
+  /// Change the frequency. None for silence.
+  pub fn set(&self, frequency: Option<f32>, now: Instant) {
+    self.state.frequency.store(frequency.unwrap_or(0.0).to_bits(), Ordering::Relaxed);
+    *self.state.updated.lock().unwrap() = now;
+  }
+
+  /// Current frequency. None while silent or after no update for TONE_TIMEOUT.
+  pub fn frequency(&self, now: Instant) -> Option<f32> {
+    if now.saturating_duration_since(*self.state.updated.lock().unwrap()) > TONE_TIMEOUT {
+      return None;
+    }
+    let frequency = f32::from_bits(self.state.frequency.load(Ordering::Relaxed));
+    (frequency > 0.0).then_some(frequency)
+  }
+
+  /// End the ToneSource.
+  pub fn stop(&self) {
+    self.state.stopped.store(true, Ordering::Relaxed);
+  }
+
+  pub fn source(&self) -> ToneSource {
+    ToneSource { control: self.clone(), frequency: None, phase: 0.0, sample: 0 }
+  }
+}
+
+/// A sine wave following the ToneControl. The phase is kept across frequency changes so that the changes do not click.
+pub struct ToneSource {
+  control: ToneControl,
+  frequency: Option<f32>,
+  phase: f32,
+  /// Index of the sample within the 10ms between the checks of the control.
+  sample: u32,
+}
+
+impl Iterator for ToneSource {
+  type Item = f32;
+
+  fn next(&mut self) -> Option<f32> {
+    // Check the control every 10ms.
+    if self.sample == 0 {
+      if self.control.state.stopped.load(Ordering::Relaxed) {
+        return None;
+      }
+      self.frequency = self.control.frequency(Instant::now());
+    }
+    self.sample = (self.sample + 1) % (SAMPLE_RATE / 100);
+    match self.frequency {
+      Some(frequency) => {
+        self.phase = (self.phase + TAU * frequency / SAMPLE_RATE as f32) % TAU;
+        Some(self.phase.sin() * 0.3)
+      }
+      None => Some(0.0),
+    }
+  }
+}
+
+impl Source for ToneSource {
+  fn current_frame_len(&self) -> Option<usize> {
+    None
+  }
+
+  fn channels(&self) -> u16 {
+    1
+  }
+
+  fn sample_rate(&self) -> u32 {
+    SAMPLE_RATE
+  }
+
+  fn total_duration(&self) -> Option<Duration> {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+  use crate::reading::Reading;
+  use super::{ToneConfig, ToneControl};
+
+  #[test]
+  fn continuity() {
+    let tone = ToneConfig::continuity(None);
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"109853802")), None);
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"000123802")), Some(2000.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"000125802")), Some(2000.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"012345802")), None);
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"560003902")), None);
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"05000;80:")), None);
+  }
+
+  #[test]
+  fn pitch() {
+    let tone = ToneConfig::pitch(None, Some(10.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"00000;80:")), Some(220.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"05000;80:")), Some(622.25397));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"11000;80:")), Some(1760.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"12000;80:")), Some(1760.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"560003902")), None);
+    let tone = ToneConfig::pitch(None, None);
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"00000;80:")), Some(220.0));
+    assert_eq!(tone.frequency(&Reading::parse_frame(b"06000;80:")), Some(1760.0));
+  }
+
+  #[test]
+  fn control_timeout() {
+    let control = ToneControl::new();
+    let now = Instant::now();
+    control.set(Some(440.0), now);
+    assert_eq!(control.frequency(now), Some(440.0));
+    assert_eq!(control.frequency(now + Duration::from_secs(2)), None);
+    control.set(None, now);
+    assert_eq!(control.frequency(now), None);
+  }
+}
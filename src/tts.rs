@@ -0,0 +1,562 @@
+use std::{collections::{HashMap, VecDeque}, fmt, fs, str::FromStr, io::Write, path::PathBuf, process::{self, Stdio}, sync::atomic::{AtomicUsize, Ordering}};
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_VOICEVOX_SPEAKER: usize = 1;
+pub const DEFAULT_OPEN_JTALK_DICTIONARY: &str = "/var/lib/mecab/dic/open-jtalk/naist-jdic";
+pub const DEFAULT_OPEN_JTALK_VOICE: &str = "/usr/share/hts-voice/nitech-jp-atr503-m001/nitech_jp_atr503_m001.htsvoice";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TtsErr {
+  Request(String),
+  Command(String),
+}
+
+impl fmt::Display for TtsErr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TtsErr::Request(msg) => write!(f, "Request to the speech engine failed: {}", msg),
+      TtsErr::Command(msg) => write!(f, "Speech synthesis command failed: {}", msg),
+    }
+  }
+}
+
+/// Speaker of VOICEVOX. An ID, or a name with an optional style ("name/style").
+#[derive(Debug, Clone, PartialEq)]
+pub enum Speaker {
+  Id(usize),
+  Name { name: String, style: Option<String> },
+}
+
+impl Default for Speaker {
+  fn default() -> Self {
+    Speaker::Id(DEFAULT_VOICEVOX_SPEAKER)
+  }
+}
+
+impl fmt::Display for Speaker {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Speaker::Id(id) => write!(f, "{}", id),
+      Speaker::Name { name, style: None } => write!(f, "{}", name),
+      Speaker::Name { name, style: Some(style) } => write!(f, "{}/{}", name, style),
+    }
+  }
+}
+
+impl FromStr for Speaker {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    if s.is_empty() {
+      return Err("Speaker is empty.".to_owned());
+    }
+    if let Ok(id) = s.parse::<usize>() {
+      return Ok(Speaker::Id(id));
+    }
+    Ok(match s.split_once('/') {
+      Some((name, style)) => Speaker::Name { name: name.trim().to_owned(), style: Some(style.trim().to_owned()) },
+      None => Speaker::Name { name: s.to_owned(), style: None },
+    })
+  }
+}
+
+impl<'de> Deserialize<'de> for Speaker {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IdOrName {
+      Id(usize),
+      Name(String),
+    }
+    match IdOrName::deserialize(d)? {
+      IdOrName::Id(id) => Ok(Speaker::Id(id)),
+      IdOrName::Name(name) => name.parse().map_err(serde::de::Error::custom),
+    }
+  }
+}
+
+/// A speaker listed by the /speakers endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpeakerInfo {
+  pub name: String,
+  pub styles: Vec<StyleInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StyleInfo {
+  pub name: String,
+  pub id: usize,
+}
+
+impl Speaker {
+  /// The ID of the speaker in the list. The first style is used if the style is omitted.
+  pub fn resolve(&self, speakers: &[SpeakerInfo]) -> Result<usize, String> {
+    match self {
+      Speaker::Id(id) => {
+        if speakers.iter().any(|s| s.styles.iter().any(|style| style.id == *id)) {
+          Ok(*id)
+        } else {
+          Err(format!("Speaker ID {} is not found.", id))
+        }
+      }
+      Speaker::Name { name, style } => {
+        let speaker = speakers.iter().find(|s| &s.name == name).ok_or(format!("Speaker '{}' is not found.", name))?;
+        let found = match style {
+          Some(style) => speaker.styles.iter().find(|s| &s.name == style),
+          None => speaker.styles.first(),
+        };
+        found.map(|s| s.id).ok_or(format!("Speaker '{}' has no style '{}'.", name, style.clone().unwrap_or_default()))
+      }
+    }
+  }
+}
+
+/// List the speakers of the VOICEVOX compatible engine.
+pub fn list_speakers(url: &str) -> Result<Vec<SpeakerInfo>, TtsErr> {
+  let url = format!("{}/speakers", url.trim_end_matches('/'));
+  let json = reqwest::blocking::get(&url)
+    .and_then(|r| r.error_for_status())
+    .and_then(|r| r.text())
+    .map_err(|e| TtsErr::Request(format!("{}: {}", url, e)))?;
+  serde_json::from_str(&json).map_err(|e| TtsErr::Request(format!("{}: {}", url, e)))
+}
+
+/// Text-to-speech engine producing WAV.
+pub trait TtsBackend: Send {
+  fn synthesize(&mut self, text: &str) -> Result<Vec<u8>, TtsErr>;
+
+  /// Identifies the engine and its parameters so that the cached audio is not shared between different voices.
+  fn voice(&self) -> String;
+
+  /// Check that the engine is available. Returns the description of the engine.
+  fn check(&mut self) -> Result<String, TtsErr>;
+}
+
+/// Text-to-speech engine to use.
+///
+/// ```toml
+/// tts = { engine = "voicevox", url = "http://localhost:50021", speaker = 3 }
+/// tts = { engine = "espeak-ng", voice = "en-us" }
+/// tts = { engine = "open-jtalk" }
+/// tts = { engine = "command", command = ["say", "-o", "{output}", "--data-format=LEI16@22050", "{text}"] }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "engine", rename_all = "kebab-case", rename_all_fields = "kebab-case", deny_unknown_fields)]
+pub enum TtsConfig {
+  /// VOICEVOX compatible REST server.
+  Voicevox {
+    url: String,
+    #[serde(default)]
+    speaker: Option<Speaker>,
+    #[serde(flatten)]
+    tuning: VoiceTuning,
+  },
+  EspeakNg {
+    #[serde(default)]
+    voice: Option<String>,
+  },
+  OpenJtalk {
+    /// HTS voice file.
+    #[serde(default)]
+    voice: Option<PathBuf>,
+    /// Dictionary directory.
+    #[serde(default)]
+    dictionary: Option<PathBuf>,
+  },
+  /// External command. See CommandTts.
+  Command { command: Vec<String> },
+}
+
+impl TtsConfig {
+  /// Keys of all the engines including the tag. A flattened TtsConfig takes only these keys from the enclosing table.
+  pub const KEYS: &'static [&'static str] = &[
+    "engine", "url", "speaker", "speed", "pitch", "intonation", "volume", "pre-silence", "post-silence", "voice", "dictionary", "command",
+  ];
+
+  pub fn backend(&self) -> Box<dyn TtsBackend> {
+    match self {
+      TtsConfig::Voicevox { url, speaker, tuning } => {
+        let speaker = match speaker {
+          Some(Speaker::Id(id)) => *id,
+          Some(speaker) => {
+            warn!("Speaker '{}' is not resolved to an ID. The speaker {} is used.", speaker, DEFAULT_VOICEVOX_SPEAKER);
+            DEFAULT_VOICEVOX_SPEAKER
+          }
+          None => DEFAULT_VOICEVOX_SPEAKER,
+        };
+        Box::new(Voicevox::new(url, speaker, tuning.clone()))
+      }
+      TtsConfig::EspeakNg { voice } => {
+        let mut command = vec!["espeak-ng".to_owned()];
+        if let Some(voice) = voice {
+          command.extend(["-v".to_owned(), voice.clone()]);
+        }
+        command.extend(["--stdout".to_owned(), "{text}".to_owned()]);
+        Box::new(CommandTts::new(command))
+      }
+      TtsConfig::OpenJtalk { voice, dictionary } => Box::new(CommandTts::new(vec![
+        "open_jtalk".to_owned(),
+        "-x".to_owned(), dictionary.clone().unwrap_or(DEFAULT_OPEN_JTALK_DICTIONARY.into()).display().to_string(),
+        "-m".to_owned(), voice.clone().unwrap_or(DEFAULT_OPEN_JTALK_VOICE.into()).display().to_string(),
+        "-ow".to_owned(), "{output}".to_owned(),
+      ])),
+      TtsConfig::Command { command } => Box::new(CommandTts::new(command.clone())),
+    }
+  }
+}
+
+/// Voice parameters of the VOICEVOX audio_query. The engine defaults are used for the omitted ones.
+///
+/// ```toml
+/// voice-tuning = { speed = 1.5, pitch = 0.0, intonation = 1.0, volume = 1.0, pre-silence = 0.05, post-silence = 0.05 }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VoiceTuning {
+  /// Speed scale. 1.0 is the normal speed.
+  pub speed: Option<f64>,
+  /// Pitch scale. 0.0 is the normal pitch.
+  pub pitch: Option<f64>,
+  /// Intonation scale. 1.0 is the normal intonation.
+  pub intonation: Option<f64>,
+  /// Volume scale. 1.0 is the normal volume.
+  pub volume: Option<f64>,
+  /// Silence before the speech in seconds.
+  pub pre_silence: Option<f64>,
+  /// Silence after the speech in seconds.
+  pub post_silence: Option<f64>,
+}
+
+impl VoiceTuning {
+  /// Fill the parameters not set in self with the ones in lower.
+  pub fn or(self, lower: VoiceTuning) -> VoiceTuning {
+    VoiceTuning {
+      speed: self.speed.or(lower.speed),
+      pitch: self.pitch.or(lower.pitch),
+      intonation: self.intonation.or(lower.intonation),
+      volume: self.volume.or(lower.volume),
+      pre_silence: self.pre_silence.or(lower.pre_silence),
+      post_silence: self.post_silence.or(lower.post_silence),
+    }
+  }
+
+  fn parameters(&self) -> [(&'static str, Option<f64>); 6] {
+    [
+      ("speedScale", self.speed),
+      ("pitchScale", self.pitch),
+      ("intonationScale", self.intonation),
+      ("volumeScale", self.volume),
+      ("prePhonemeLength", self.pre_silence),
+      ("postPhonemeLength", self.post_silence),
+    ]
+  }
+
+  /// Set the parameters into the audio_query JSON.
+  pub fn patch(&self, query: &str) -> Result<String, TtsErr> {
+    if self.parameters().iter().all(|(_, v)| v.is_none()) {
+      return Ok(query.to_owned());
+    }
+    let mut json: serde_json::Value = serde_json::from_str(query).map_err(|e| TtsErr::Request(format!("Invalid audio query: {}", e)))?;
+    let object = json.as_object_mut().ok_or(TtsErr::Request("Invalid audio query: not an object".to_owned()))?;
+    for (name, value) in self.parameters() {
+      if let Some(value) = value {
+        object.insert(name.to_owned(), value.into());
+      }
+    }
+    Ok(json.to_string())
+  }
+}
+
+/// VOICEVOX compatible engine. Synthesizes with the audio_query and synthesis endpoints.
+pub struct Voicevox {
+  url: String,
+  speaker: usize,
+  tuning: VoiceTuning,
+  /// Created on the first request, in the thread speaking.
+  client: Option<reqwest::blocking::Client>,
+}
+
+impl Voicevox {
+  pub fn new(url: &str, speaker: usize, tuning: VoiceTuning) -> Self {
+    Self { url: url.trim_end_matches('/').to_owned(), speaker, tuning, client: None }
+  }
+
+  fn post(&mut self, url: reqwest::Url, body: Option<String>) -> Result<reqwest::blocking::Response, TtsErr> {
+    let request = self.client.get_or_insert_with(reqwest::blocking::Client::new).post(url.clone());
+    let request = match body {
+      Some(body) => request.header("Content-Type", "application/json").body(body),
+      None => request,
+    };
+    request.send()
+      .and_then(|r| r.error_for_status())
+      .map_err(|e| TtsErr::Request(format!("{}: {}", url, e)))
+  }
+}
+
+impl TtsBackend for Voicevox {
+  fn synthesize(&mut self, text: &str) -> Result<Vec<u8>, TtsErr> {
+    let speaker = self.speaker.to_string();
+    let url = reqwest::Url::parse_with_params(&format!("{}/audio_query", self.url), &[("speaker", speaker.as_str()), ("text", text)])
+      .map_err(|e| TtsErr::Request(format!("{}: {}", self.url, e)))?;
+    let query = self.post(url, None)?.text().map_err(|e| TtsErr::Request(e.to_string()))?;
+    let query = self.tuning.patch(&query)?;
+
+    let url = reqwest::Url::parse_with_params(&format!("{}/synthesis", self.url), &[("speaker", speaker.as_str())])
+      .map_err(|e| TtsErr::Request(format!("{}: {}", self.url, e)))?;
+    let wav = self.post(url, Some(query))?.bytes().map_err(|e| TtsErr::Request(e.to_string()))?;
+    Ok(wav.into())
+  }
+
+  fn voice(&self) -> String {
+    let parameters: Vec<String> = self.tuning.parameters().iter()
+      .filter_map(|(name, value)| value.map(|v| format!("{}={}", name, v)))
+      .collect();
+    format!("voicevox {} {} {}", self.url, self.speaker, parameters.join(" "))
+  }
+
+  fn check(&mut self) -> Result<String, TtsErr> {
+    let url = format!("{}/version", self.url);
+    let version = self.client.get_or_insert_with(reqwest::blocking::Client::new).get(&url).send()
+      .and_then(|r| r.error_for_status())
+      .and_then(|r| r.text())
+      .map_err(|e| TtsErr::Request(format!("{}: {}", url, e)))?;
+    Ok(format!("VOICEVOX {} at {} (speaker {})", version.trim().trim_matches('"'), self.url, self.speaker))
+  }
+}
+
+/// External command. "{text}" in the arguments is replaced with the text, otherwise the text is written to the standard
+/// input. "{output}" is replaced with a temporary WAV file to be read, otherwise the WAV is read from the standard output.
+pub struct CommandTts {
+  command: Vec<String>,
+}
+
+static TEMPORARY_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+impl CommandTts {
+  pub fn new(command: Vec<String>) -> Self {
+    Self { command }
+  }
+}
+
+impl TtsBackend for CommandTts {
+  fn synthesize(&mut self, text: &str) -> Result<Vec<u8>, TtsErr> {
+    let (program, args) = self.command.split_first().ok_or(TtsErr::Command("No command is specified.".to_owned()))?;
+    let output: Option<PathBuf> = if args.iter().any(|a| a.contains("{output}")) {
+      let n = TEMPORARY_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+      Some(std::env::temp_dir().join(format!("m6000m-{}-{}.wav", process::id(), n)))
+    } else {
+      None
+    };
+    let text_in_args = args.iter().any(|a| a.contains("{text}"));
+    let args: Vec<String> = args.iter().map(|a| {
+      let a = a.replace("{text}", text);
+      match &output {
+        Some(path) => a.replace("{output}", &path.display().to_string()),
+        None => a,
+      }
+    }).collect();
+
+    let mut child = process::Command::new(program)
+      .args(&args)
+      .stdin(if text_in_args { Stdio::null() } else { Stdio::piped() })
+      .stdout(if output.is_some() { Stdio::null() } else { Stdio::piped() })
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| TtsErr::Command(format!("{}: {}", program, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(text.as_bytes()).map_err(|e| TtsErr::Command(format!("{}: {}", program, e)))?;
+    }
+    let result = child.wait_with_output().map_err(|e| TtsErr::Command(format!("{}: {}", program, e)));
+    let wav = match (&output, result) {
+      (_, Err(err)) => Err(err),
+      (_, Ok(result)) if !result.status.success() => {
+        Err(TtsErr::Command(format!("{} exited with {}: {}", program, result.status, String::from_utf8_lossy(&result.stderr).trim())))
+      }
+      (Some(path), Ok(_)) => fs::read(path).map_err(|e| TtsErr::Command(format!("Cannot read {}: {}", path.display(), e))),
+      (None, Ok(result)) => Ok(result.stdout),
+    };
+    if let Some(path) = &output {
+      let _ = fs::remove_file(path);
+    }
+    wav
+  }
+
+  fn voice(&self) -> String {
+    self.command.join(" ")
+  }
+
+  fn check(&mut self) -> Result<String, TtsErr> {
+    let program = self.command.first().ok_or(TtsErr::Command("No command is specified.".to_owned()))?;
+    let found = if program.contains(std::path::MAIN_SEPARATOR) {
+      PathBuf::from(program).is_file()
+    } else {
+      std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+    };
+    if found {
+      Ok(self.command.join(" "))
+    } else {
+      Err(TtsErr::Command(format!("{}: command not found", program)))
+    }
+  }
+}
+
+pub const DEFAULT_MEMORY_CACHE_CAPACITY: usize = 256;
+
+/// Caches the synthesized audio of the backend by the voice and the text, in memory and optionally in a directory.
+/// The oldest audio is removed from the memory when the capacity is exceeded. The directory is never cleaned.
+pub struct CachedTts {
+  inner: Box<dyn TtsBackend>,
+  capacity: usize,
+  memory: HashMap<String, Vec<u8>>,
+  order: VecDeque<String>,
+  directory: Option<PathBuf>,
+}
+
+impl CachedTts {
+  pub fn new(inner: Box<dyn TtsBackend>, capacity: usize, directory: Option<PathBuf>) -> Self {
+    if let Some(dir) = &directory {
+      if let Err(err) = fs::create_dir_all(dir) {
+        warn!("Cannot create the speech cache directory {}: {}", dir.display(), err);
+      }
+    }
+    Self { inner, capacity, memory: HashMap::new(), order: VecDeque::new(), directory }
+  }
+
+  fn remember(&mut self, key: String, wav: Vec<u8>) {
+    if self.capacity == 0 {
+      return;
+    }
+    while self.capacity <= self.order.len() {
+      if let Some(oldest) = self.order.pop_front() {
+        self.memory.remove(&oldest);
+      }
+    }
+    self.order.push_back(key.clone());
+    self.memory.insert(key, wav);
+  }
+}
+
+impl TtsBackend for CachedTts {
+  fn synthesize(&mut self, text: &str) -> Result<Vec<u8>, TtsErr> {
+    let key: String = Sha256::digest(format!("{}\n{}", self.inner.voice(), text).as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    if let Some(wav) = self.memory.get(&key) {
+      return Ok(wav.clone());
+    }
+    let file = self.directory.as_ref().map(|dir| dir.join(format!("{}.wav", key)));
+    if let Some(wav) = file.as_ref().and_then(|f| fs::read(f).ok()) {
+      self.remember(key, wav.clone());
+      return Ok(wav);
+    }
+
+    let wav = self.inner.synthesize(text)?;
+    if let Some(file) = &file {
+      if let Err(err) = fs::write(file, &wav) {
+        warn!("Cannot write the speech cache {}: {}", file.display(), err);
+      }
+    }
+    self.remember(key, wav.clone());
+    Ok(wav)
+  }
+
+  fn voice(&self) -> String {
+    self.inner.voice()
+  }
+
+  fn check(&mut self) -> Result<String, TtsErr> {
+    self.inner.check()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+  use super::{CachedTts, CommandTts, Speaker, SpeakerInfo, StyleInfo, TtsBackend, TtsErr, VoiceTuning};
+
+  /// Returns the text as the audio and counts the calls.
+  struct Echo(Arc<AtomicUsize>);
+
+  impl TtsBackend for Echo {
+    fn synthesize(&mut self, text: &str) -> Result<Vec<u8>, TtsErr> {
+      self.0.fetch_add(1, Ordering::Relaxed);
+      Ok(text.as_bytes().to_vec())
+    }
+
+    fn voice(&self) -> String {
+      "echo".to_owned()
+    }
+
+    fn check(&mut self) -> Result<String, TtsErr> {
+      Ok("echo".to_owned())
+    }
+  }
+
+  #[test]
+  fn speaker() {
+    let speakers = vec![
+      SpeakerInfo { name: "四国めたん".to_owned(), styles: vec![StyleInfo { name: "ノーマル".to_owned(), id: 2 }, StyleInfo { name: "あまあま".to_owned(), id: 0 }] },
+      SpeakerInfo { name: "ずんだもん".to_owned(), styles: vec![StyleInfo { name: "ノーマル".to_owned(), id: 3 }] },
+    ];
+    assert_eq!("3".parse::<Speaker>().unwrap(), Speaker::Id(3));
+    assert_eq!("3".parse::<Speaker>().unwrap().resolve(&speakers), Ok(3));
+    assert!("1".parse::<Speaker>().unwrap().resolve(&speakers).is_err());
+    assert_eq!("四国めたん".parse::<Speaker>().unwrap().resolve(&speakers), Ok(2));
+    assert_eq!("四国めたん/あまあま".parse::<Speaker>().unwrap().resolve(&speakers), Ok(0));
+    assert!("四国めたん/ツンツン".parse::<Speaker>().unwrap().resolve(&speakers).is_err());
+    assert!("春日部つむぎ".parse::<Speaker>().unwrap().resolve(&speakers).is_err());
+    assert_eq!("ずんだもん/ノーマル".parse::<Speaker>().unwrap().to_string(), "ずんだもん/ノーマル");
+  }
+
+  #[test]
+  fn tuning() {
+    let query = r#"{"accent_phrases":[],"speedScale":1.0,"pitchScale":0.0,"volumeScale":1.0}"#;
+    assert_eq!(VoiceTuning::default().patch(query).unwrap(), query);
+    let tuning = VoiceTuning { speed: Some(1.5), post_silence: Some(0.0), ..Default::default() };
+    let patched: serde_json::Value = serde_json::from_str(&tuning.patch(query).unwrap()).unwrap();
+    assert_eq!(patched["speedScale"], 1.5);
+    assert_eq!(patched["postPhonemeLength"], 0.0);
+    assert_eq!(patched["pitchScale"], 0.0);
+    assert!(tuning.patch("[]").is_err());
+  }
+
+  #[test]
+  fn cache() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut tts = CachedTts::new(Box::new(Echo(calls.clone())), 2, None);
+    assert_eq!(tts.synthesize("a").unwrap(), b"a");
+    assert_eq!(tts.synthesize("a").unwrap(), b"a");
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    tts.synthesize("b").unwrap();
+    tts.synthesize("c").unwrap();
+    tts.synthesize("a").unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 4);
+
+    let dir = std::env::temp_dir().join(format!("m6000m-cache-test-{}", std::process::id()));
+    let calls = Arc::new(AtomicUsize::new(0));
+    CachedTts::new(Box::new(Echo(calls.clone())), 0, Some(dir.clone())).synthesize("d").unwrap();
+    assert_eq!(CachedTts::new(Box::new(Echo(calls.clone())), 0, Some(dir.clone())).synthesize("d").unwrap(), b"d");
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn command() {
+    let mut tts = CommandTts::new(vec!["echo".to_owned(), "-n".to_owned(), "{text}".to_owned()]);
+    assert_eq!(tts.synthesize("hello").unwrap(), b"hello");
+
+    let mut tts = CommandTts::new(vec!["cat".to_owned()]);
+    assert_eq!(tts.synthesize("stdin").unwrap(), b"stdin");
+
+    let mut tts = CommandTts::new(vec!["sh".to_owned(), "-c".to_owned(), "printf '%s' \"$0\" > \"$1\"".to_owned(), "{text}".to_owned(), "{output}".to_owned()]);
+    assert_eq!(tts.synthesize("file").unwrap(), b"file");
+
+    let mut tts = CommandTts::new(vec!["false".to_owned()]);
+    assert!(matches!(tts.synthesize("x"), Err(TtsErr::Command(_))));
+    assert!(tts.check().is_ok());
+    assert!(CommandTts::new(vec!["m6000m-no-such-command".to_owned()]).check().is_err());
+  }
+}
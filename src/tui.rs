@@ -37,14 +37,14 @@ impl Tui {
                             } else {
                                 eprintln!("Invalid selection. Please select a number between 1 and {}", available_ports.len());
                             }
-                            eprintln!("");
+                            eprintln!();
                         } else {
                             return Some(available_ports[n - 1].clone());
                         }
                     }
                     Err(err) => {
                       eprintln!("Error: invalid input. Input number or q: quit. {:?}", err);
-                      eprintln!("");
+                      eprintln!();
                     }
                 }
             }
@@ -56,6 +56,6 @@ impl Tui {
 impl Tui {
     pub fn ask_port(&mut self, available_ports: Vec<SerialPortInfo>) -> Option<SerialPortInfo> {
       self.available_ports = Some(available_ports.clone());
-      return self.port_to_return.clone();
+      self.port_to_return.clone()
     }
 }
@@ -0,0 +1,59 @@
+use std::collections::{HashMap, HashSet};
+use m6000m_rs::tts::{self, SpeakerInfo, TtsConfig};
+use crate::{output::{OutputConfig, OutputKind}, AppErr};
+
+/// Print the speakers of the VOICEVOX engine.
+pub fn list_speakers(url: &str) -> Result<(), AppErr> {
+    let speakers = tts::list_speakers(url).map_err(|e| AppErr::VoiceError(e.to_string()))?;
+    for speaker in speakers {
+        println!("{}", speaker.name);
+        for style in speaker.styles {
+            println!("  {:>4}  {}/{}", style.id, speaker.name, style.name);
+        }
+    }
+    Ok(())
+}
+
+/// Check that the text-to-speech engines are available before measuring.
+pub fn check_engines(outputs: &[OutputConfig], voice: &Option<TtsConfig>) -> Result<(), AppErr> {
+    let mut checked = HashSet::new();
+    let engines = outputs.iter()
+        .filter_map(|o| match &o.kind {
+            OutputKind::Speech { tts, .. } => Some(tts),
+            _ => None,
+        })
+        .chain(voice.as_ref());
+    for tts in engines {
+        let mut backend = tts.backend();
+        if !checked.insert(backend.voice()) {
+            continue;
+        }
+        let engine = backend.check().map_err(|e| AppErr::VoiceError(format!("Text-to-speech engine is not available. {}", e)))?;
+        eprintln!("{}", serde_json::json!({ "voice": { "status": "ok", "engine": engine } }));
+    }
+    Ok(())
+}
+
+/// Replace the speakers of the VOICEVOX engines with the IDs checked with the engines.
+pub fn resolve_speakers(outputs: &mut [OutputConfig], voice: &mut Option<TtsConfig>) -> Result<(), AppErr> {
+    let mut speakers: HashMap<String, Vec<SpeakerInfo>> = HashMap::new();
+    let engines = outputs.iter_mut()
+        .filter_map(|o| match &mut o.kind {
+            OutputKind::Speech { tts, .. } => Some(tts),
+            _ => None,
+        })
+        .chain(voice.as_mut());
+    for tts in engines {
+        let TtsConfig::Voicevox { url, speaker, .. } = tts else { continue };
+        let Some(s) = speaker else { continue };
+        if !speakers.contains_key(url) {
+            let list = tts::list_speakers(url).map_err(|e| AppErr::VoiceError(e.to_string()))?;
+            speakers.insert(url.clone(), list);
+        }
+        let id = s.resolve(&speakers[url]).map_err(|e| {
+            AppErr::VoiceError(format!("{} Run the list-speakers command to see the speakers of {}.", e, url))
+        })?;
+        *speaker = Some(tts::Speaker::Id(id));
+    }
+    Ok(())
+}